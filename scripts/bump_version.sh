@@ -36,10 +36,6 @@ fi
 echo "Updating version in Cargo.toml..."
 sed -i "s/^version = \"$CURRENT_VERSION\"/version = \"$NEW_VERSION\"/" Cargo.toml
 
-# Update version in doc/manual.md
-echo "Updating version in doc/manual.md..."
-sed -i "s/% HAI(1) hai $CURRENT_VERSION/% HAI(1) hai $NEW_VERSION/" doc/manual.md
-
 # Update version in doc/config.md
 echo "Updating version in doc/config.md..."
 sed -i "s/% HAI-CONFIG(5) hai $CURRENT_VERSION/% HAI-CONFIG(5) hai $NEW_VERSION/" doc/config.md
@@ -0,0 +1,186 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// How much attached data is sent in all, shared between the attachments,
+/// so that a big log file doesn't make the request slow and expensive
+const MAX_ATTACHED_BYTES: usize = 8192;
+
+/// Files are read up to this size, which is more than enough for a sample
+const MAX_FILE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Data the user wants the command to work on: piped to hai or named with
+/// `--file`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    /// Where the data came from, as shown to the model
+    pub source: String,
+    pub content: String,
+}
+
+impl Attachment {
+    pub fn stdin(content: String) -> Attachment {
+        Attachment {
+            source: "Data piped to hai".to_string(),
+            content,
+        }
+    }
+
+    /// Reads a text file. Binary files are refused rather than sent as
+    /// garbage.
+    pub fn file(path: &Path) -> Result<Attachment> {
+        let mut bytes = Vec::new();
+        File::open(path)
+            .and_then(|file| file.take(MAX_FILE_BYTES).read_to_end(&mut bytes))
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if bytes.contains(&0) {
+            return Err(anyhow::anyhow!(
+                "{} looks like a binary file, only text files can be attached",
+                path.display()
+            ));
+        }
+
+        Ok(Attachment {
+            source: format!("Contents of {}", path.display()),
+            content: String::from_utf8_lossy(&bytes).into_owned(),
+        })
+    }
+}
+
+/// The request sent to the model: the prompt followed by a sample of each
+/// attachment
+pub fn request(prompt: &str, attachments: &[Attachment]) -> String {
+    let mut request = prompt.trim().to_string();
+    if attachments.is_empty() {
+        return request;
+    }
+
+    let budget = MAX_ATTACHED_BYTES / attachments.len();
+    for attachment in attachments {
+        let content = attachment.content.trim_end();
+        let (sample, note) = if content.len() > budget {
+            (sample(content, budget), " (a sample, the rest is left out)")
+        } else {
+            (content.to_string(), "")
+        };
+        request.push_str(&format!(
+            "\n\n{}{}:\n```\n{}\n```",
+            attachment.source, note, sample
+        ));
+    }
+
+    request.push_str(
+        "\n\nThe data is for reference, so that the command fits its format. \
+         Don't include it in the command.",
+    );
+    request
+}
+
+/// The start and end of a text in about `max_bytes`, cut at line breaks,
+/// with a marker where lines were left out
+fn sample(text: &str, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text.to_string();
+    }
+
+    let lines: Vec<&str> = text.lines().collect();
+    let head_budget = max_bytes * 3 / 4;
+    let tail_budget = max_bytes - head_budget;
+
+    let mut head = 0;
+    let mut used = 0;
+    while head < lines.len() && used + lines[head].len() < head_budget {
+        used += lines[head].len() + 1;
+        head += 1;
+    }
+
+    let mut tail = lines.len();
+    used = 0;
+    while tail > head && used + lines[tail - 1].len() < tail_budget {
+        tail -= 1;
+        used += lines[tail].len() + 1;
+    }
+
+    // A single line longer than the budget is cut instead
+    if head == 0 && tail == lines.len() {
+        let mut end = head_budget;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        return format!("{}[...]", &text[..end]);
+    }
+
+    let mut sample = lines[..head].join("\n");
+    sample.push_str(&format!("\n[... {} lines left out ...]\n", tail - head));
+    sample.push_str(&lines[tail..].join("\n"));
+    sample
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Cli;
+    use clap::Parser;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_request() {
+        assert_eq!(request(" list files \n", &[]), "list files");
+
+        let log = Attachment::stdin("10.0.0.1 - - [12/Mar/2025] \"GET / HTTP/1.1\" 200\n".into());
+        let request = request("count unique IPs", &[log]);
+        assert!(request.starts_with(
+            "count unique IPs\n\nData piped to hai:\n```\n10.0.0.1 - - [12/Mar/2025]"
+        ));
+        assert!(request.contains("200\n```\n\n"));
+        assert!(!request.contains("a sample"));
+    }
+
+    #[test]
+    fn test_sample() {
+        let text: String = (0..1000).map(|i| format!("line {}\n", i)).collect();
+        let sample = sample(&text, 400);
+        assert!(sample.len() <= 450);
+        assert!(sample.starts_with("line 0\nline 1\n"));
+        assert!(sample.ends_with("line 998\nline 999"));
+        assert!(sample.contains(" lines left out ...]\n"));
+
+        let long_line = "x".repeat(1000);
+        assert_eq!(
+            super::sample(&long_line, 100),
+            format!("{}[...]", "x".repeat(75))
+        );
+
+        let attachment = Attachment::stdin(text);
+        assert!(request("parse this", &[attachment]).contains("(a sample, the rest is left out)"));
+    }
+
+    #[test]
+    fn test_file() {
+        let dir = tempdir().unwrap();
+        let csv = dir.path().join("users.csv");
+        std::fs::write(&csv, "id,name\n1,ada\n").unwrap();
+        let attachment = Attachment::file(&csv).unwrap();
+        assert_eq!(attachment.source, format!("Contents of {}", csv.display()));
+        assert_eq!(attachment.content, "id,name\n1,ada\n");
+
+        let binary = dir.path().join("a.out");
+        std::fs::write(&binary, [0x7f, b'E', b'L', b'F', 0, 0]).unwrap();
+        assert!(Attachment::file(&binary).is_err());
+        assert!(Attachment::file(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_cli_files() {
+        let cli =
+            Cli::try_parse_from(["hai", "-f", "a.csv", "--file", "b.csv", "sum col 2"]).unwrap();
+        assert_eq!(
+            cli.files,
+            vec![PathBuf::from("a.csv"), PathBuf::from("b.csv")]
+        );
+        // Files are only attached to a single request
+        assert!(Cli::try_parse_from(["hai", "-i", "-f", "a.csv"]).is_err());
+    }
+}
@@ -0,0 +1,84 @@
+use clap::builder::{PossibleValue, PossibleValuesParser};
+use clap::Command;
+use clap_complete::Shell;
+
+/// The completion script for a shell. `--model` completes to the given model
+/// names, which are read when the script is generated. The PowerShell and
+/// Elvish scripts only complete subcommands and options.
+pub fn script(shell: Shell, cmd: Command, models: &[String]) -> String {
+    let mut cmd = with_models(cmd, models);
+    let name = cmd.get_name().to_string();
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut cmd, name, &mut script);
+    String::from_utf8_lossy(&script).into_owned()
+}
+
+/// Offers the model names as the values of every `--model`, including the
+/// ones of subcommands
+fn with_models(mut cmd: Command, models: &[String]) -> Command {
+    if models.is_empty() {
+        return cmd;
+    }
+
+    if cmd.get_arguments().any(|arg| arg.get_id() == "model") {
+        let values = models.iter().map(|model| PossibleValue::new(model.clone()));
+        cmd = cmd.mut_arg("model", |arg| {
+            arg.value_parser(PossibleValuesParser::new(values))
+        });
+    }
+
+    let subcommands: Vec<String> = cmd
+        .get_subcommands()
+        .map(|sub| sub.get_name().to_string())
+        .collect();
+    for name in subcommands {
+        cmd = cmd.mut_subcommand(name, |sub| with_models(sub, models));
+    }
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cli, Commands};
+    use clap::{Arg, CommandFactory, Parser};
+
+    fn command() -> Command {
+        Command::new("hai")
+            .arg(Arg::new("model").short('m').long("model"))
+            .subcommand(Command::new("fix").arg(Arg::new("model").long("model")))
+            .subcommand(Command::new("undo"))
+    }
+
+    #[test]
+    fn test_model_names() {
+        let models = vec!["claude-sonnet".to_string(), "gpt-4o".to_string()];
+
+        let fish = script(Shell::Fish, command(), &models);
+        assert_eq!(fish.matches("claude-sonnet").count(), 2);
+        assert!(fish.contains("gpt-4o"));
+
+        for shell in [Shell::Bash, Shell::Zsh] {
+            assert!(script(shell, command(), &models).contains("claude-sonnet"));
+        }
+        assert!(!script(Shell::Bash, command(), &[]).contains("claude-sonnet"));
+    }
+
+    #[test]
+    fn test_cli_completions() {
+        let cli = Cli::try_parse_from(["hai", "completions", "powershell"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Completions {
+                shell: Shell::PowerShell
+            })
+        ));
+        assert!(Cli::try_parse_from(["hai", "completions", "csh"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_definition() {
+        // Completions and man pages are generated from the same definition
+        Cli::command().debug_assert();
+    }
+}
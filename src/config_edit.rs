@@ -0,0 +1,336 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+use toml_edit::{DocumentMut, Item, Key, Table, TableLike, Value};
+
+use crate::config::{Config, MODEL_SETTINGS, PROJECT_SETTINGS, SETTINGS};
+use crate::error::HaiError;
+
+/// Settings whose values are always strings, so that an API key made of
+/// digits isn't written as a number
+const STRING_SETTINGS: [&str; 10] = [
+    "default-model",
+    "shell",
+    "system-prompt",
+    "system-prompt-append",
+    "shell-mode",
+    "provider",
+    "model",
+    "auth-token",
+    "auth-token-env",
+    "auth-token-command",
+];
+
+/// Splits a key such as `temperature` or `models."gpt-4.1".auth-token` into
+/// its parts, using TOML's dotted key syntax
+pub fn parse_key(key: &str) -> Result<Vec<String>> {
+    let keys =
+        Key::parse(key).map_err(|e| HaiError::config(format!("Invalid key '{}': {}", key, e)))?;
+    Ok(keys.iter().map(|key| key.get().to_string()).collect())
+}
+
+/// Reads a config file for editing, or starts an empty one
+pub fn load(path: &Path) -> Result<DocumentMut> {
+    if !path.exists() {
+        return Ok(DocumentMut::new());
+    }
+
+    let config_str =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    config_str.parse().map_err(|e| {
+        HaiError::config(format!(
+            "Failed to parse config file {}: {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
+/// Sets a setting, keeping the comments and layout of the rest of the file.
+/// A comment after the old value is kept as well.
+pub fn set(doc: &mut DocumentMut, key: &str, value: &str) -> Result<()> {
+    let keys = parse_key(key)?;
+    check_setting(&keys, key)?;
+    let name = keys.last().unwrap();
+    let mut value = parse_value(name, value);
+    if keys.len() == 1 {
+        check_type(name, &value)?;
+    }
+
+    let mut table: &mut dyn TableLike = doc.as_table_mut();
+    for parent in &keys[..keys.len() - 1] {
+        table = table
+            .entry(parent)
+            .or_insert_with(|| {
+                let mut table = Table::new();
+                table.set_implicit(true);
+                Item::Table(table)
+            })
+            .as_table_like_mut()
+            .ok_or_else(|| HaiError::config(format!("{} is not a table", parent)))?;
+    }
+
+    if let Some(Item::Value(old)) = table.get_mut(name) {
+        *value.decor_mut() = old.decor().clone();
+        *old = value;
+    } else {
+        table.insert(name, Item::Value(value));
+    }
+    Ok(())
+}
+
+/// Removes a setting or a whole table such as `models.gpt`. Returns whether
+/// it was set.
+pub fn unset(doc: &mut DocumentMut, key: &str) -> Result<bool> {
+    let keys = parse_key(key)?;
+    let mut table: &mut dyn TableLike = doc.as_table_mut();
+    for parent in &keys[..keys.len() - 1] {
+        match table.get_mut(parent).and_then(Item::as_table_like_mut) {
+            Some(child) => table = child,
+            None => return Ok(false),
+        }
+    }
+    Ok(table.remove(keys.last().unwrap()).is_some())
+}
+
+/// Refuses settings that a project's .hai.toml can't set, which would be
+/// ignored, and above all API keys, since the file is meant to be shared
+pub fn check_project_setting(key: &str) -> Result<()> {
+    let keys = parse_key(key)?;
+    if keys
+        .last()
+        .is_some_and(|name| name.starts_with("auth-token"))
+    {
+        return Err(HaiError::config(format!(
+            "Refusing to write {} to a project's .hai.toml, which is meant to be shared. \
+             Set it in your own config file instead.",
+            key
+        )));
+    }
+    if keys.len() != 1 || !PROJECT_SETTINGS.contains(&keys[0].as_str()) {
+        return Err(HaiError::config(format!(
+            "A project's .hai.toml can't set {}, only {}",
+            key,
+            PROJECT_SETTINGS.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// Whether a setting holds an API key, which is masked unless asked for
+pub fn is_secret(key: &str) -> bool {
+    key.ends_with("auth-token")
+}
+
+/// Looks up a setting in the merged config
+pub fn get<'a>(table: &'a toml::Table, keys: &[String]) -> Option<&'a toml::Value> {
+    let (last, parents) = keys.split_last()?;
+    let mut table = table;
+    for parent in parents {
+        table = table.get(parent)?.as_table()?;
+    }
+    table.get(last)
+}
+
+/// The settings in a table as `key = value` lines, with tables flattened
+/// into dotted keys and API keys masked unless `show_secrets` is set
+pub fn list(table: &toml::Table, prefix: &str, show_secrets: bool) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            format_key(key)
+        } else {
+            format!("{}.{}", prefix, format_key(key))
+        };
+        match value {
+            toml::Value::Table(table) => lines.extend(list(table, &key, show_secrets)),
+            value if is_secret(&key) && !show_secrets => lines.push(format!(
+                "{} = \"{}\"",
+                key,
+                mask(value.as_str().unwrap_or_default())
+            )),
+            value => lines.push(format!("{} = {}", key, value)),
+        }
+    }
+    lines
+}
+
+/// Writes a key as it would be in a dotted key, quoted if needed
+pub fn format_key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if bare {
+        key.to_string()
+    } else {
+        format!("{:?}", key)
+    }
+}
+
+/// Hides all but the end of an API key
+pub fn mask(token: &str) -> String {
+    if token.is_empty() {
+        return String::new();
+    }
+    if token.len() < 12 {
+        return "****".to_string();
+    }
+    let start = token.char_indices().rev().nth(3).map_or(0, |(i, _)| i);
+    format!("****{}", &token[start..])
+}
+
+/// Refuses keys that hai doesn't know, which would be ignored
+fn check_setting(keys: &[String], key: &str) -> Result<()> {
+    let known = match keys {
+        [name] => name != "models" && SETTINGS.contains(&name.as_str()),
+        [models, _, name] => models == "models" && MODEL_SETTINGS.contains(&name.as_str()),
+        _ => false,
+    };
+    if known {
+        Ok(())
+    } else {
+        Err(HaiError::config(format!(
+            "Unknown setting '{}', see hai-config(5) for the settings",
+            key
+        )))
+    }
+}
+
+/// Reads a value as TOML, so that `0.5`, `true` and `["a", "b"]` keep their
+/// type, and anything else as a string
+fn parse_value(name: &str, value: &str) -> Value {
+    if STRING_SETTINGS.contains(&name) {
+        return Value::from(value);
+    }
+    value.parse().unwrap_or_else(|_| Value::from(value))
+}
+
+/// Checks that a value has the right type for a setting, e.g. that
+/// temperature is a number
+fn check_type(name: &str, value: &Value) -> Result<()> {
+    toml::from_str::<Config>(&format!("{} = {}", name, value))
+        .map(|_| ())
+        .map_err(|e| HaiError::config(format!("Invalid value for {}: {}", name, e.message())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cli, Commands, ConfigAction};
+    use clap::Parser;
+    use tempfile::tempdir;
+
+    const CONFIG: &str = r#"# Temperature affects randomness (0.0 to 1.0)
+temperature = 0.3
+
+[models.gpt-4o-mini]
+provider = "openai"
+auth-token = ""  # Add your OpenAI API key here
+"#;
+
+    #[test]
+    fn test_set() {
+        let mut doc: DocumentMut = CONFIG.parse().unwrap();
+        set(&mut doc, "temperature", "0.5").unwrap();
+        set(&mut doc, "models.gpt-4o-mini.auth-token", "12345").unwrap();
+        set(&mut doc, "models.\"gpt-4.1\".provider", "openai").unwrap();
+        set(&mut doc, "models.\"gpt-4.1\".auth-token", "sk-proj").unwrap();
+        set(&mut doc, "preview", "true").unwrap();
+        set(&mut doc, "default-model", "gpt-4.1").unwrap();
+
+        let text = doc.to_string();
+        assert!(
+            text.starts_with("# Temperature affects randomness (0.0 to 1.0)\ntemperature = 0.5\n")
+        );
+        assert!(text.contains("auth-token = \"12345\"  # Add your OpenAI API key here\n"));
+        assert!(text
+            .contains("[models.\"gpt-4.1\"]\nprovider = \"openai\"\nauth-token = \"sk-proj\"\n"));
+        assert!(!text.contains("[models]\n"));
+        assert!(text.contains("default-model = \"gpt-4.1\"\n"));
+
+        let config: Config = toml::from_str(&text).unwrap();
+        assert!(config.preview());
+        assert_eq!(config.temperature(), 0.5);
+
+        assert!(set(&mut doc, "temprature", "0.5").is_err());
+        assert!(set(&mut doc, "temperature", "hot").is_err());
+        assert!(set(&mut doc, "models.gpt-4o-mini.token", "x").is_err());
+        assert!(set(&mut doc, "models", "x").is_err());
+    }
+
+    #[test]
+    fn test_unset() {
+        let mut doc: DocumentMut = CONFIG.parse().unwrap();
+        assert!(unset(&mut doc, "temperature").unwrap());
+        assert!(!unset(&mut doc, "temperature").unwrap());
+        assert!(!unset(&mut doc, "models.claude.auth-token").unwrap());
+        assert!(unset(&mut doc, "models.gpt-4o-mini").unwrap());
+        assert!(!doc.to_string().contains("temperature ="));
+        assert!(!doc.to_string().contains("provider"));
+    }
+
+    #[test]
+    fn test_get_and_list() {
+        let table: toml::Table = toml::from_str(
+            "preview = true\n[models.\"gpt-4.1\"]\nprovider = \"openai\"\nauth-token = \"sk-proj-abcdefgh1234\"\n",
+        )
+        .unwrap();
+        let keys = parse_key("models.\"gpt-4.1\".provider").unwrap();
+        assert_eq!(keys, ["models", "gpt-4.1", "provider"]);
+        assert_eq!(get(&table, &keys).and_then(|v| v.as_str()), Some("openai"));
+        assert_eq!(get(&table, &parse_key("shell").unwrap()), None);
+
+        assert_eq!(
+            list(&table, "", false),
+            vec![
+                "models.\"gpt-4.1\".auth-token = \"****1234\"",
+                "models.\"gpt-4.1\".provider = \"openai\"",
+                "preview = true",
+            ]
+        );
+        assert!(list(&table, "", true)
+            .contains(&"models.\"gpt-4.1\".auth-token = \"sk-proj-abcdefgh1234\"".to_string()));
+        assert_eq!(mask("short"), "****");
+        assert_eq!(mask(""), "");
+    }
+
+    #[test]
+    fn test_check_project_setting() {
+        assert!(check_project_setting("preview").is_ok());
+        assert!(check_project_setting("default-model").is_ok());
+        let error = check_project_setting("models.x.auth-token").unwrap_err();
+        assert!(error.to_string().contains("meant to be shared"));
+        assert!(check_project_setting("models.x.auth-token-command").is_err());
+        assert!(check_project_setting("models.x.provider").is_err());
+        assert!(check_project_setting("shell").is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        assert!(load(&path).unwrap().is_empty());
+        fs::write(&path, "temperature = [").unwrap();
+        assert!(load(&path).is_err());
+    }
+
+    #[test]
+    fn test_cli_config_set_project() {
+        let cli = Cli::try_parse_from([
+            "hai",
+            "config",
+            "set",
+            "--project",
+            "default-model",
+            "claude-3",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Config {
+                action: ConfigAction::Set { project: true, .. }
+            })
+        ));
+    }
+}
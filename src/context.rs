@@ -0,0 +1,301 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Upper bound on the summary, so that a big directory doesn't crowd out the
+/// request or make it expensive
+const MAX_CONTEXT_BYTES: usize = 2048;
+
+/// Entries listed before the rest of the directory is only counted
+const MAX_FILES: usize = 40;
+
+/// Make targets and package scripts listed per project
+const MAX_TASKS: usize = 15;
+
+/// Files that give away what kind of project a directory holds
+const PROJECT_FILES: [(&str, &str); 16] = [
+    ("Cargo.toml", "Rust (Cargo)"),
+    ("package.json", "JavaScript (npm)"),
+    ("pyproject.toml", "Python"),
+    ("setup.py", "Python"),
+    ("requirements.txt", "Python"),
+    ("go.mod", "Go"),
+    ("pom.xml", "Java (Maven)"),
+    ("build.gradle", "Java (Gradle)"),
+    ("build.gradle.kts", "Kotlin (Gradle)"),
+    ("CMakeLists.txt", "C/C++ (CMake)"),
+    ("meson.build", "Meson"),
+    ("Makefile", "Make"),
+    ("Gemfile", "Ruby (Bundler)"),
+    ("composer.json", "PHP (Composer)"),
+    ("Dockerfile", "Docker"),
+    ("docker-compose.yml", "Docker Compose"),
+];
+
+/// Lock files that tell which package manager a JavaScript project uses
+const NODE_LOCK_FILES: [(&str, &str); 4] = [
+    ("pnpm-lock.yaml", "pnpm"),
+    ("yarn.lock", "yarn"),
+    ("bun.lockb", "bun"),
+    ("package-lock.json", "npm"),
+];
+
+/// A short description of a directory for the system prompt: its files, the
+/// kind of project and how to build it, and the state of its git checkout
+pub fn collect(dir: &Path) -> String {
+    let mut lines = vec![format!("Current directory: {}", dir.display())];
+
+    if let Some(files) = file_names(dir) {
+        lines.push(format!("Files: {}", files));
+    }
+
+    let projects = project_types(dir);
+    if !projects.is_empty() {
+        lines.push(format!("Project type: {}", projects.join(", ")));
+    }
+
+    let targets = make_targets(dir);
+    if !targets.is_empty() {
+        lines.push(format!("Make targets: {}", targets.join(", ")));
+    }
+
+    if let Some((manager, scripts)) = package_scripts(dir) {
+        lines.push(format!("{} scripts: {}", manager, scripts.join(", ")));
+    }
+
+    if let Some(git) = git_status(dir) {
+        lines.push(format!("Git: {}", git));
+    }
+
+    truncate(&lines.join("\n"), MAX_CONTEXT_BYTES)
+}
+
+/// The names in the directory, directories marked with a `/`. Hidden files
+/// are left out.
+fn file_names(dir: &Path) -> Option<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if name.starts_with('.') {
+                return None;
+            }
+            let is_dir = entry.file_type().is_ok_and(|kind| kind.is_dir());
+            Some(if is_dir { format!("{}/", name) } else { name })
+        })
+        .collect();
+    if names.is_empty() {
+        return None;
+    }
+    names.sort();
+
+    let hidden = names.len().saturating_sub(MAX_FILES);
+    names.truncate(MAX_FILES);
+    let mut files = names.join(", ");
+    if hidden > 0 {
+        files.push_str(&format!(" (and {} more)", hidden));
+    }
+    Some(files)
+}
+
+fn project_types(dir: &Path) -> Vec<String> {
+    let mut projects: Vec<String> = Vec::new();
+    for (file, project) in PROJECT_FILES {
+        if dir.join(file).is_file() && !projects.iter().any(|p| p == project) {
+            projects.push(project.to_string());
+        }
+    }
+    projects
+}
+
+/// The targets a Makefile defines, without pattern rules and special
+/// targets such as `.PHONY`
+fn make_targets(dir: &Path) -> Vec<String> {
+    let Ok(makefile) = fs::read_to_string(dir.join("Makefile")) else {
+        return Vec::new();
+    };
+
+    let mut targets: Vec<String> = Vec::new();
+    for line in makefile.lines() {
+        // Recipes are indented and variables are assigned with `:=` or `::=`
+        if line.starts_with(['\t', ' ', '.', '#']) {
+            continue;
+        }
+        let Some((names, rest)) = line.split_once(':') else {
+            continue;
+        };
+        if rest.starts_with(['=', ':']) {
+            continue;
+        }
+        for name in names.split_whitespace() {
+            if !name.contains(['%', '$', '=']) && !targets.iter().any(|t| t == name) {
+                targets.push(name.to_string());
+            }
+        }
+    }
+    targets.truncate(MAX_TASKS);
+    targets
+}
+
+/// The package manager of a JavaScript project and the scripts in its
+/// package.json
+fn package_scripts(dir: &Path) -> Option<(&'static str, Vec<String>)> {
+    let package = fs::read_to_string(dir.join("package.json")).ok()?;
+    let package: serde_json::Value = serde_json::from_str(&package).ok()?;
+    let mut scripts: Vec<String> = package
+        .get("scripts")?
+        .as_object()?
+        .keys()
+        .cloned()
+        .collect();
+    if scripts.is_empty() {
+        return None;
+    }
+    scripts.truncate(MAX_TASKS);
+
+    let manager = NODE_LOCK_FILES
+        .iter()
+        .find(|(file, _)| dir.join(file).is_file())
+        .map_or("npm", |(_, manager)| manager);
+    Some((manager, scripts))
+}
+
+/// The current branch and how many files are changed, or `None` outside a
+/// git checkout
+fn git_status(dir: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["status", "--porcelain", "--branch"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(summarize_git_status(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// Summarizes the output of `git status --porcelain --branch`
+fn summarize_git_status(status: &str) -> String {
+    let mut lines = status.lines();
+    let header = lines
+        .next()
+        .and_then(|line| line.strip_prefix("## "))
+        .unwrap_or_default();
+    let branch = match header.strip_prefix("No commits yet on ") {
+        Some(branch) => branch,
+        None => header.split("...").next().unwrap_or_default(),
+    };
+    let branch = match branch.split_whitespace().next() {
+        Some("HEAD") | None => "a detached HEAD".to_string(),
+        Some(branch) => format!("branch {}", branch),
+    };
+
+    let (mut changed, mut untracked) = (0, 0);
+    for line in lines {
+        if line.starts_with("??") {
+            untracked += 1;
+        } else {
+            changed += 1;
+        }
+    }
+
+    match (changed, untracked) {
+        (0, 0) => format!("on {}, no changes", branch),
+        (changed, 0) => format!("on {}, {} changed", branch, changed),
+        (0, untracked) => format!("on {}, {} untracked", branch, untracked),
+        (changed, untracked) => format!(
+            "on {}, {} changed and {} untracked",
+            branch, changed, untracked
+        ),
+    }
+}
+
+/// Cuts text to at most `max` bytes, at the end of a line if possible
+fn truncate(text: &str, max: usize) -> String {
+    if text.len() <= max {
+        return text.to_string();
+    }
+
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let cut = &text[..end];
+    cut.rfind('\n')
+        .map_or(cut, |newline| &cut[..newline])
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_collect() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("Cargo.toml"), "[package]\n").unwrap();
+        fs::write(
+            dir.path().join("Makefile"),
+            ".PHONY: all test\nVERSION := 1.0\nall: build\n\ttouch all\nbuild test: src\n%.o: %.c\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("package.json"),
+            r#"{"scripts": {"lint": "eslint .", "test": "jest"}}"#,
+        )
+        .unwrap();
+        fs::write(dir.path().join("yarn.lock"), "").unwrap();
+        fs::write(dir.path().join(".env"), "SECRET=1").unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+
+        let context = collect(dir.path());
+        assert!(context.starts_with(&format!("Current directory: {}\n", dir.path().display())));
+        assert!(context.contains("\nFiles: Cargo.toml, Makefile, package.json, src/, yarn.lock\n"));
+        assert!(context.contains("\nProject type: Rust (Cargo), JavaScript (npm), Make\n"));
+        assert!(context.contains("\nMake targets: all, build, test\n"));
+        assert!(context.contains("\nyarn scripts: lint, test"));
+    }
+
+    #[test]
+    fn test_many_files() {
+        let dir = tempdir().unwrap();
+        for i in 0..MAX_FILES + 5 {
+            fs::write(dir.path().join(format!("file{:03}.txt", i)), "").unwrap();
+        }
+
+        let files = file_names(dir.path()).unwrap();
+        assert!(files.starts_with("file000.txt, file001.txt"));
+        assert!(files.ends_with("file039.txt (and 5 more)"));
+    }
+
+    #[test]
+    fn test_summarize_git_status() {
+        assert_eq!(
+            summarize_git_status(
+                "## main...origin/main [ahead 1]\n M src/main.rs\nA  new.rs\n?? notes.txt\n"
+            ),
+            "on branch main, 2 changed and 1 untracked"
+        );
+        assert_eq!(
+            summarize_git_status("## No commits yet on trunk\n"),
+            "on branch trunk, no changes"
+        );
+        assert_eq!(
+            summarize_git_status("## HEAD (no branch)\n?? a\n"),
+            "on a detached HEAD, 1 untracked"
+        );
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short", 100), "short");
+        assert_eq!(truncate("first line\nsecond line", 15), "first line");
+        assert_eq!(truncate("ééé", 3), "é");
+    }
+}
@@ -37,22 +37,20 @@ impl HaiError {
         anyhow::Error::new(HaiError::Serialization(msg.into()))
     }
 
-    // These functions are defined but not currently used in the code.
-    // They're provided for completeness and future use.
-    #[allow(dead_code)]
-    pub fn api<S: Into<String>>(msg: S) -> anyhow::Error {
-        anyhow::Error::new(HaiError::ApiCommunication(msg.into()))
-    }
-
-    #[allow(dead_code)]
     pub fn command<S: Into<String>>(msg: S) -> anyhow::Error {
         anyhow::Error::new(HaiError::CommandExecution(msg.into()))
     }
 
-    #[allow(dead_code)]
     pub fn other<S: Into<String>>(msg: S) -> anyhow::Error {
         anyhow::Error::new(HaiError::Other(msg.into()))
     }
+
+    // This function is defined but not currently used in the code.
+    // It's provided for completeness and future use.
+    #[allow(dead_code)]
+    pub fn api<S: Into<String>>(msg: S) -> anyhow::Error {
+        anyhow::Error::new(HaiError::ApiCommunication(msg.into()))
+    }
 }
 
 /// Converts anyhow::Error to HaiError by examining the error chain
@@ -0,0 +1,380 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a captured stream may stay quiet after the command exits before
+/// hai stops waiting for it. Programs the command started in the background
+/// (`cmd &`, nohup) keep the stream open, and would otherwise make hai wait
+/// for them.
+const QUIET_AFTER_EXIT: Duration = Duration::from_millis(200);
+
+/// What happened when a command was run
+#[derive(Debug, Clone)]
+pub struct Execution {
+    /// Exit code, or `None` if the command was killed by a signal
+    pub exit_code: Option<i32>,
+    pub duration: Duration,
+    pub cwd: Option<PathBuf>,
+    /// The end of standard output, if it was captured
+    pub stdout: Option<String>,
+    /// The end of standard error, if it was captured
+    pub stderr: Option<String>,
+}
+
+impl Execution {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    /// Turns a non-zero exit into an error
+    pub fn check(&self, command: &str) -> Result<()> {
+        if !self.success() {
+            return Err(anyhow::anyhow!(
+                "Command '{}' failed with exit code: {}",
+                command,
+                self.exit_code.unwrap_or(-1)
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// How many bytes from the end of each output stream to keep. A stream with a
+/// limit of 0 is not captured and goes straight to the terminal, which
+/// interactive programs need.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capture {
+    pub stdout: usize,
+    pub stderr: usize,
+}
+
+/// How the shell that runs a command is started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ShellMode {
+    /// A plain `shell -c`, without the user's rc files
+    #[default]
+    Command,
+    /// An interactive shell, which loads aliases and functions from the rc
+    /// files
+    Interactive,
+    /// A login shell, which loads the profile
+    Login,
+}
+
+/// Arguments that come before the command. PowerShell always runs the same
+/// way.
+fn shell_args(shell: &str, mode: ShellMode) -> &'static [&'static str] {
+    match (shell, mode) {
+        ("powershell" | "pwsh", _) => &["-Command"],
+        (_, ShellMode::Command) => &["-c"],
+        (_, ShellMode::Interactive) => &["-i", "-c"],
+        (_, ShellMode::Login) => &["-l", "-c"],
+    }
+}
+
+/// Runs a command with the given shell. An error is only returned if the
+/// command could not be started; its exit status is part of the result.
+///
+/// Captured streams are still shown as the command runs.
+pub fn execute_command(
+    command: &str,
+    shell: &str,
+    mode: ShellMode,
+    capture: Capture,
+) -> Result<Execution> {
+    // Run the command using the specified shell
+    let mut cmd = Command::new(shell);
+    let context = match shell {
+        "powershell" | "pwsh" => "Failed to execute PowerShell command",
+        "fish" => "Failed to execute Fish command",
+        _ => "Failed to execute command",
+    };
+    cmd.args(shell_args(shell, mode)).arg(command);
+
+    if capture.stdout > 0 {
+        cmd.stdout(Stdio::piped());
+    }
+    if capture.stderr > 0 {
+        cmd.stderr(Stdio::piped());
+    }
+
+    let cwd = std::env::current_dir().ok();
+    let start = Instant::now();
+    let mut child = cmd.spawn().context(context)?;
+
+    let stdout = child
+        .stdout
+        .take()
+        .map(|out| tee(out, io::stdout(), capture.stdout));
+    let stderr = child
+        .stderr
+        .take()
+        .map(|err| tee(err, io::stderr(), capture.stderr));
+
+    let status = child.wait().context(context)?;
+    let duration = start.elapsed();
+
+    Ok(Execution {
+        exit_code: status.code(),
+        duration,
+        cwd,
+        stdout: stdout.map(Tee::finish),
+        stderr: stderr.map(Tee::finish),
+    })
+}
+
+/// Runs a command with `input` on its standard input, for `--pipe`. Its
+/// output goes straight to hai's standard output, and the shell is always a
+/// plain `shell -c`, so that rc files can't add to the output.
+pub fn execute_filter(command: &str, shell: &str, input: Vec<u8>) -> Result<Execution> {
+    let mut cmd = Command::new(shell);
+    cmd.args(shell_args(shell, ShellMode::Command))
+        .arg(command)
+        .stdin(Stdio::piped());
+
+    let cwd = std::env::current_dir().ok();
+    let start = Instant::now();
+    let mut child = cmd.spawn().context("Failed to execute command")?;
+
+    // Written from another thread, so that a command that writes before it
+    // has read everything can't block. Commands such as `head` may stop
+    // reading early, which is not an error.
+    let writer = child.stdin.take().map(|mut stdin| {
+        thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        })
+    });
+
+    let status = child.wait().context("Failed to execute command")?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+
+    Ok(Execution {
+        exit_code: status.code(),
+        duration: start.elapsed(),
+        cwd,
+        stdout: None,
+        stderr: None,
+    })
+}
+
+/// A stream being copied to the terminal on its own thread, keeping the end
+/// of what passed through
+struct Tee {
+    captured: Arc<Mutex<Vec<u8>>>,
+    limit: usize,
+    /// Receives a message for every read, and is closed at the end of the
+    /// stream
+    activity: Receiver<()>,
+}
+
+impl Tee {
+    /// The last `limit` bytes of the stream once it has ended, or has been
+    /// quiet for a moment since the command exited. The thread goes on
+    /// copying whatever background programs write after that.
+    fn finish(self) -> String {
+        // Ends with a timeout, or when the stream is closed
+        while self.activity.recv_timeout(QUIET_AFTER_EXIT).is_ok() {}
+
+        let captured = self.captured.lock().unwrap();
+        let start = captured.len().saturating_sub(self.limit);
+        String::from_utf8_lossy(&captured[start..]).into_owned()
+    }
+}
+
+/// Copies everything from `reader` to `writer` on another thread, keeping
+/// the last `limit` bytes that passed through
+fn tee<R, W>(mut reader: R, mut writer: W, limit: usize) -> Tee
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let captured = Arc::new(Mutex::new(Vec::new()));
+    let (sender, activity) = mpsc::channel();
+
+    let shared = Arc::clone(&captured);
+    thread::spawn(move || {
+        let mut buffer = [0u8; 8192];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let _ = writer.write_all(&buffer[..n]);
+                    let _ = writer.flush();
+
+                    let mut captured = shared.lock().unwrap();
+                    captured.extend_from_slice(&buffer[..n]);
+                    if captured.len() > limit * 2 {
+                        let excess = captured.len() - limit;
+                        captured.drain(..excess);
+                    }
+                    drop(captured);
+                    // Nobody listens once the command has been dealt with
+                    let _ = sender.send(());
+                }
+            }
+        }
+    });
+
+    Tee {
+        captured,
+        limit,
+        activity,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_execute_command() {
+        // Test with a simple command that should always succeed
+        let result = execute_command("echo test", "bash", ShellMode::Command, Capture::default());
+        assert!(result.is_ok());
+        let execution = result.unwrap();
+        assert!(execution.success());
+        assert!(execution.check("echo test").is_ok());
+        assert!(execution.stdout.is_none());
+
+        // Test with a command that should fail
+        let execution =
+            execute_command("exit 1", "bash", ShellMode::Command, Capture::default()).unwrap();
+        assert!(!execution.success());
+        assert_eq!(execution.exit_code, Some(1));
+        assert!(execution.check("exit 1").is_err());
+
+        // A shell that doesn't exist can't run anything
+        assert!(execute_command(
+            "echo test",
+            "no-such-shell",
+            ShellMode::Command,
+            Capture::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_execute_command_with_different_shells() {
+        // This test is limited since we can't easily mock Command execution
+        // But we can at least test that the function doesn't panic with different shells
+
+        // Test with bash (should be available on most test systems)
+        let result = execute_command(
+            "echo 'test'",
+            "bash",
+            ShellMode::Command,
+            Capture::default(),
+        );
+        assert!(result.is_ok());
+
+        // We can't reliably test fish or powershell as they might not be installed
+        // on the test system, but the code path should be covered by the implementation
+    }
+
+    #[test]
+    fn test_execute_command_captures_output() {
+        let capture = Capture {
+            stdout: 1024,
+            stderr: 1024,
+        };
+        let execution = execute_command(
+            "echo out; echo err >&2; exit 3",
+            "bash",
+            ShellMode::Command,
+            capture,
+        )
+        .unwrap();
+        assert_eq!(execution.exit_code, Some(3));
+        assert_eq!(execution.stdout.as_deref(), Some("out\n"));
+        assert_eq!(execution.stderr.as_deref(), Some("err\n"));
+        assert_eq!(execution.cwd, std::env::current_dir().ok());
+
+        // Streams can be captured independently
+        let capture = Capture {
+            stdout: 0,
+            stderr: 1024,
+        };
+        let execution =
+            execute_command("echo err >&2", "bash", ShellMode::Command, capture).unwrap();
+        assert!(execution.stdout.is_none());
+        assert_eq!(execution.stderr.as_deref(), Some("err\n"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_execute_command_with_background_program() {
+        let capture = Capture {
+            stdout: 1024,
+            stderr: 1024,
+        };
+        // The background sleep keeps both pipes open long after the shell
+        // exits
+        let start = Instant::now();
+        let execution = execute_command(
+            "sleep 5 & echo started",
+            "bash",
+            ShellMode::Command,
+            capture,
+        )
+        .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(3));
+        assert!(execution.success());
+        assert_eq!(execution.stdout.as_deref(), Some("started\n"));
+    }
+
+    #[test]
+    fn test_execute_filter() {
+        let input = b"apple\nbanana\n".to_vec();
+        let execution = execute_filter("grep -q banana", "bash", input.clone()).unwrap();
+        assert!(execution.success());
+        let execution = execute_filter("grep -q cherry", "bash", input).unwrap();
+        assert_eq!(execution.exit_code, Some(1));
+
+        // A command that doesn't read its input still finishes
+        let big = vec![b'x'; 1024 * 1024];
+        assert!(execute_filter("true", "bash", big).unwrap().success());
+    }
+
+    #[test]
+    fn test_shell_modes() {
+        assert_eq!(shell_args("bash", ShellMode::Command), ["-c"]);
+        assert_eq!(shell_args("zsh", ShellMode::Interactive), ["-i", "-c"]);
+        assert_eq!(shell_args("fish", ShellMode::Login), ["-l", "-c"]);
+        assert_eq!(shell_args("pwsh", ShellMode::Login), ["-Command"]);
+
+        let capture = Capture {
+            stdout: 1024,
+            stderr: 0,
+        };
+        let execution = execute_command(
+            "shopt -q login_shell && echo login",
+            "bash",
+            ShellMode::Login,
+            capture,
+        )
+        .unwrap();
+        // The profile may print something too
+        assert!(execution.stdout.unwrap().ends_with("login\n"));
+    }
+
+    #[test]
+    fn test_capture_keeps_the_end_of_the_output() {
+        let capture = Capture {
+            stdout: 6,
+            stderr: 0,
+        };
+        let execution =
+            execute_command("seq 1 10000", "bash", ShellMode::Command, capture).unwrap();
+        assert_eq!(execution.stdout.as_deref(), Some("10000\n"));
+    }
+}
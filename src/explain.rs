@@ -0,0 +1,143 @@
+use crate::config::Config;
+
+/// Explanations are much longer than a single command
+pub const EXPLAIN_MAX_TOKENS: usize = 1024;
+
+/// System prompt for `hai explain`
+pub const EXPLAIN_SYSTEM_PROMPT: &str = "
+You are Hai, a helpful AI that explains shell commands.
+The user gives you a command. Do not run it and do not suggest a different command.
+On the first line, summarize in one sentence what the whole command does.
+Then write one line for each part of the command, in the order they appear: the program, each flag and argument, each pipe stage and each redirection.
+Write each of those lines as the part exactly as it appears in the command, followed by \" :: \" and a short explanation.
+Mention anything that deletes or overwrites data, needs elevated privileges or uses the network.
+Do not use markdown formatting.
+";
+
+/// Room for a sentence or two describing a suggested command
+pub const DESCRIBE_MAX_TOKENS: usize = 200;
+
+/// System prompt for the description shown with `--explain`
+pub const DESCRIBE_SYSTEM_PROMPT: &str = "
+You are Hai, a helpful AI that explains shell commands.
+The user gives you a command they are about to run. Do not suggest a different command.
+In one or two plain sentences, describe what the command will do, including anything it deletes, overwrites or changes.
+Do not use markdown formatting.
+";
+
+/// Parts wider than this are put on their own line
+const MAX_PART_WIDTH: usize = 30;
+
+/// A command broken down into its parts
+#[derive(Debug, PartialEq, Eq)]
+pub struct Explanation {
+    pub summary: String,
+    pub parts: Vec<Part>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Part {
+    pub text: String,
+    pub description: String,
+}
+
+/// The system prompt for explaining a command, with the user's environment
+pub fn system_prompt(config: &Config) -> String {
+    config.with_environment(EXPLAIN_SYSTEM_PROMPT)
+}
+
+/// The system prompt for describing a suggested command
+pub fn describe_system_prompt(config: &Config) -> String {
+    config.with_environment(DESCRIBE_SYSTEM_PROMPT)
+}
+
+/// Parses the model's answer. Lines that aren't `part :: explanation` are
+/// kept as part of the summary, so nothing the model said is lost.
+pub fn parse_explanation(response: &str) -> Explanation {
+    let mut summary = Vec::new();
+    let mut parts = Vec::new();
+
+    for line in response.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        // Some models turn the parts into a list anyway
+        let line = line
+            .strip_prefix("- ")
+            .or_else(|| line.strip_prefix("* "))
+            .unwrap_or(line);
+        match line.split_once(" :: ") {
+            Some((text, description)) => parts.push(Part {
+                text: text.trim().trim_matches('`').to_string(),
+                description: description.trim().to_string(),
+            }),
+            None => summary.push(line),
+        }
+    }
+
+    Explanation {
+        summary: summary.join("\n"),
+        parts,
+    }
+}
+
+pub fn print_explanation(explanation: &Explanation) {
+    if !explanation.summary.is_empty() {
+        println!("{}", explanation.summary);
+    }
+    if explanation.parts.is_empty() {
+        return;
+    }
+
+    let width = explanation
+        .parts
+        .iter()
+        .map(|part| part.text.chars().count())
+        .filter(|&width| width <= MAX_PART_WIDTH)
+        .max()
+        .unwrap_or(0);
+
+    println!();
+    for part in &explanation.parts {
+        if part.text.chars().count() > width {
+            println!("  {}", part.text);
+            println!("  {:width$}  {}", "", part.description, width = width);
+        } else {
+            println!(
+                "  {:width$}  {}",
+                part.text,
+                part.description,
+                width = width
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_explanation() {
+        let response = "Finds log files older than a week and deletes them.\n\
+                        \n\
+                        find . :: search the current directory recursively\n\
+                        - `-name '*.log'` :: only files ending in .log\n\
+                        -mtime +7 :: modified more than 7 days ago\n\
+                        | xargs rm :: delete every file found\n";
+
+        let explanation = parse_explanation(response);
+        assert_eq!(
+            explanation.summary,
+            "Finds log files older than a week and deletes them."
+        );
+        assert_eq!(explanation.parts.len(), 4);
+        assert_eq!(explanation.parts[1].text, "-name '*.log'");
+        assert_eq!(explanation.parts[2].text, "-mtime +7");
+        assert_eq!(explanation.parts[3].description, "delete every file found");
+    }
+
+    #[test]
+    fn test_parse_unstructured_explanation() {
+        let explanation = parse_explanation("Lists files.\nIncluding hidden ones.");
+        assert_eq!(explanation.summary, "Lists files.\nIncluding hidden ones.");
+        assert!(explanation.parts.is_empty());
+    }
+}
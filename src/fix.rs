@@ -0,0 +1,269 @@
+use anyhow::Result;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::error::HaiError;
+
+/// Bytes from the end of stderr captured so that a failed command can be sent
+/// back to the model
+pub const FIX_STDERR_BYTES: usize = 4096;
+
+/// Builds a prompt asking the model to correct a command that failed
+pub fn fix_prompt(
+    goal: Option<&str>,
+    command: &str,
+    exit_code: Option<i32>,
+    stderr: &str,
+) -> String {
+    let mut prompt = String::new();
+
+    if let Some(goal) = goal.map(str::trim).filter(|g| !g.is_empty()) {
+        prompt.push_str(&format!("The user asked for: {}\n\n", goal));
+    }
+
+    prompt.push_str(&format!("This command failed:\n{}\n", command.trim()));
+
+    if let Some(code) = exit_code {
+        prompt.push_str(&format!("\nIt exited with code {}.\n", code));
+    }
+
+    let stderr = stderr.trim();
+    if !stderr.is_empty() {
+        prompt.push_str(&format!("\nIts error output was:\n{}\n", stderr));
+    }
+
+    prompt.push_str("\nRespond with a corrected command that does what was intended.");
+    prompt
+}
+
+/// Finds the last command the user ran in their shell. A shell hook can pass
+/// it in `HAI_LAST_COMMAND`, otherwise it is read from the shell's history
+/// file, which some shells only write when they exit.
+pub fn last_shell_command(shell: &str) -> Result<String> {
+    if let Ok(command) = std::env::var("HAI_LAST_COMMAND") {
+        let command = without_hai(&command);
+        if !command.is_empty() {
+            return Ok(command);
+        }
+    }
+
+    let format = HistoryFormat::for_shell(shell);
+    let path = history_file(format).ok_or_else(|| {
+        HaiError::command(format!("Could not find the history file for {}", shell))
+    })?;
+
+    // zsh stores some characters in its own encoding, so don't insist on UTF-8
+    let contents = fs::read(&path)
+        .map_err(|e| HaiError::io(format!("Failed to read {}: {}", path.display(), e)))?;
+
+    last_command(&String::from_utf8_lossy(&contents), format).ok_or_else(|| {
+        HaiError::command(format!(
+            "No previous command found in {}. Pass the command to fix as an argument.",
+            path.display()
+        ))
+    })
+}
+
+/// Exit status of the last command, if a shell hook passed it in
+/// `HAI_LAST_STATUS`
+pub fn last_exit_code() -> Option<i32> {
+    std::env::var("HAI_LAST_STATUS").ok()?.trim().parse().ok()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HistoryFormat {
+    /// One command per line, optionally preceded by `#<timestamp>` lines
+    Bash,
+    /// `: <start>:<duration>;<command>` lines, continued with a backslash
+    Zsh,
+    /// `- cmd: <command>` entries
+    Fish,
+}
+
+impl HistoryFormat {
+    fn for_shell(shell: &str) -> Self {
+        match shell {
+            "zsh" => HistoryFormat::Zsh,
+            "fish" => HistoryFormat::Fish,
+            _ => HistoryFormat::Bash,
+        }
+    }
+}
+
+fn history_file(format: HistoryFormat) -> Option<PathBuf> {
+    if format != HistoryFormat::Fish {
+        if let Some(histfile) = std::env::var_os("HISTFILE") {
+            return Some(PathBuf::from(histfile));
+        }
+    }
+
+    let path = match format {
+        HistoryFormat::Bash => dirs::home_dir()?.join(".bash_history"),
+        HistoryFormat::Zsh => dirs::home_dir()?.join(".zsh_history"),
+        HistoryFormat::Fish => dirs::data_dir()?.join("fish").join("fish_history"),
+    };
+    path.exists().then_some(path)
+}
+
+/// Returns the most recent command in a history file that isn't a call to
+/// hai itself
+fn last_command(contents: &str, format: HistoryFormat) -> Option<String> {
+    let commands = match format {
+        HistoryFormat::Bash => contents
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(str::to_string)
+            .collect(),
+        HistoryFormat::Zsh => zsh_commands(contents),
+        HistoryFormat::Fish => contents
+            .lines()
+            .filter_map(|line| line.strip_prefix("- cmd: "))
+            .map(fish_unescape)
+            .collect(),
+    };
+
+    commands
+        .into_iter()
+        .rev()
+        .map(|command| without_hai(&command))
+        .find(|command| !command.is_empty())
+}
+
+fn zsh_commands(contents: &str) -> Vec<String> {
+    let mut commands: Vec<String> = Vec::new();
+    let mut continued = false;
+
+    for line in contents.lines() {
+        if continued {
+            if let Some(last) = commands.last_mut() {
+                last.push('\n');
+                last.push_str(line.strip_suffix('\\').unwrap_or(line));
+            }
+        } else {
+            // Extended history lines carry a timestamp before the command
+            let command = match line.strip_prefix(": ") {
+                Some(rest) => rest.split_once(';').map_or(rest, |(_, command)| command),
+                None => line,
+            };
+            commands.push(command.strip_suffix('\\').unwrap_or(command).to_string());
+        }
+        continued = line.ends_with('\\');
+    }
+
+    commands
+}
+
+/// fish writes newlines and backslashes in commands as `\n` and `\\`
+fn fish_unescape(command: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+/// Drops a call to hai from a history line: `hai fix` itself is skipped, and
+/// `make 2>&1 | hai fix` becomes `make`
+fn without_hai(command: &str) -> String {
+    let command = command.trim();
+    if is_hai(command) {
+        return String::new();
+    }
+
+    if let Some((before, after)) = command.rsplit_once('|') {
+        if is_hai(after.trim()) {
+            let before = before.trim_end();
+            return before
+                .strip_suffix("2>&1")
+                .unwrap_or(before)
+                .trim()
+                .to_string();
+        }
+    }
+
+    command.to_string()
+}
+
+fn is_hai(command: &str) -> bool {
+    command == "hai" || command.starts_with("hai ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fix_prompt() {
+        let prompt = fix_prompt(
+            Some("list files by size"),
+            "ls --sort=size -l",
+            Some(1),
+            "ls: unrecognized option '--sort=size'\n",
+        );
+        assert!(prompt.starts_with("The user asked for: list files by size\n"));
+        assert!(prompt.contains("This command failed:\nls --sort=size -l\n"));
+        assert!(prompt.contains("exited with code 1"));
+        assert!(prompt.contains("unrecognized option '--sort=size'"));
+    }
+
+    #[test]
+    fn test_fix_prompt_without_goal_or_output() {
+        let prompt = fix_prompt(None, "make", None, "  ");
+        assert!(prompt.starts_with("This command failed:\nmake\n"));
+        assert!(!prompt.contains("exited"));
+        assert!(!prompt.contains("error output"));
+    }
+
+    #[test]
+    fn test_last_command_bash() {
+        let history = "#1700000000\ngit push\n#1700000005\ncargo biuld\nhai fix\n";
+        assert_eq!(
+            last_command(history, HistoryFormat::Bash).as_deref(),
+            Some("cargo biuld")
+        );
+        assert_eq!(last_command("hai fix\n", HistoryFormat::Bash), None);
+    }
+
+    #[test]
+    fn test_last_command_zsh() {
+        let history = ": 1700000000:0;ls\n: 1700000003:0;for f in *; do\\\necho $f\\\ndone\n: 1700000009:0;hai fix\n";
+        assert_eq!(
+            last_command(history, HistoryFormat::Zsh).as_deref(),
+            Some("for f in *; do\necho $f\ndone")
+        );
+
+        // Without extended history, lines are just commands
+        assert_eq!(
+            last_command("ls\ntar xf a.tgz\n", HistoryFormat::Zsh).as_deref(),
+            Some("tar xf a.tgz")
+        );
+    }
+
+    #[test]
+    fn test_last_command_fish() {
+        let history = "- cmd: echo hi\n  when: 1700000000\n- cmd: grep -r foo \\\\.\n  when: 1700000001\n  paths:\n    - src\n";
+        assert_eq!(
+            last_command(history, HistoryFormat::Fish).as_deref(),
+            Some("grep -r foo \\.")
+        );
+    }
+
+    #[test]
+    fn test_without_hai() {
+        assert_eq!(without_hai("hai fix --exit-code 2"), "");
+        assert_eq!(without_hai("make 2>&1 | hai fix"), "make");
+        assert_eq!(without_hai("ls | grep x"), "ls | grep x");
+        assert_eq!(without_hai("haiku"), "haiku");
+    }
+}
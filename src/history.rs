@@ -1,24 +1,87 @@
 use anyhow::Result;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
 
 use crate::error::HaiError;
+use crate::executor::Execution;
+use crate::redact::Redactor;
+use crate::trash::{self, SavedFile, Snapshot};
 use crate::utils;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HistoryEntry {
     pub prompt: String,
     pub command: String,
     pub timestamp: chrono::DateTime<chrono::Utc>,
     pub executed: bool,
     pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Directory the command ran in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    /// The end of the command's output, if `history-output-size` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<String>,
+    /// Files saved to the trash before the command ran, used by `hai undo`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<Snapshot>,
+}
+
+impl HistoryEntry {
+    pub fn new(prompt: &str, command: &str, executed: bool, model: &str) -> Self {
+        Self {
+            prompt: prompt.to_string(),
+            command: command.to_string(),
+            timestamp: chrono::Utc::now(),
+            executed,
+            model: model.to_string(),
+            exit_code: None,
+            duration_ms: None,
+            cwd: None,
+            stdout: None,
+            stderr: None,
+            snapshot: None,
+        }
+    }
+
+    /// Records how the command ran, marking the entry as executed. At most
+    /// `output_limit` bytes from the end of any captured output are kept.
+    pub fn record_execution(&mut self, execution: &Execution, output_limit: usize) {
+        let tail = |output: &Option<String>| {
+            output
+                .as_deref()
+                .filter(|_| output_limit > 0)
+                .map(|output| {
+                    let mut start = output.len().saturating_sub(output_limit);
+                    while !output.is_char_boundary(start) {
+                        start += 1;
+                    }
+                    output[start..].to_string()
+                })
+        };
+
+        self.executed = true;
+        self.exit_code = execution.exit_code;
+        self.duration_ms = Some(execution.duration.as_millis() as u64);
+        self.cwd = execution.cwd.clone();
+        self.stdout = tail(&execution.stdout);
+        self.stderr = tail(&execution.stderr);
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct History {
     entries: Vec<HistoryEntry>,
     max_size: usize,
+    #[serde(skip)]
+    redactor: Redactor,
 }
 
 impl History {
@@ -26,18 +89,17 @@ impl History {
         Self {
             entries: Vec::new(),
             max_size,
+            redactor: Redactor::default(),
         }
     }
 
+    /// Add an entry built from its parts - only used for testing
+    #[cfg(test)]
     pub fn add_entry(&mut self, prompt: &str, command: &str, executed: bool, model: &str) {
-        let entry = HistoryEntry {
-            prompt: prompt.to_string(),
-            command: command.to_string(),
-            timestamp: chrono::Utc::now(),
-            executed,
-            model: model.to_string(),
-        };
+        self.push(HistoryEntry::new(prompt, command, executed, model));
+    }
 
+    pub fn push(&mut self, entry: HistoryEntry) {
         self.entries.push(entry);
 
         // Trim history if it exceeds max size
@@ -50,13 +112,77 @@ impl History {
         &self.entries
     }
 
+    /// Returns the most recent entry that still has files in the trash
+    pub fn latest_with_snapshot(&mut self) -> Option<&mut HistoryEntry> {
+        self.entries
+            .iter_mut()
+            .rev()
+            .find(|entry| entry.snapshot.is_some())
+    }
+
+    /// Remove every entry whose prompt or command matches `pattern`, returning
+    /// the number of entries removed
+    pub fn purge_matching(&mut self, pattern: &Regex) -> usize {
+        let before = self.entries.len();
+        self.entries
+            .retain(|entry| !pattern.is_match(&entry.prompt) && !pattern.is_match(&entry.command));
+        before - self.entries.len()
+    }
+
+    /// Returns a copy of the history with secrets masked in every entry
+    fn redacted(&self) -> Self {
+        let entries = self
+            .entries
+            .iter()
+            .map(|entry| HistoryEntry {
+                prompt: self.redactor.redact(&entry.prompt),
+                command: self.redactor.redact(&entry.command),
+                stdout: entry.stdout.as_deref().map(|s| self.redactor.redact(s)),
+                stderr: entry.stderr.as_deref().map(|s| self.redactor.redact(s)),
+                snapshot: entry.snapshot.as_ref().map(|s| self.redacted_snapshot(s)),
+                ..entry.clone()
+            })
+            .collect();
+
+        Self {
+            entries,
+            max_size: self.max_size,
+            redactor: self.redactor.clone(),
+        }
+    }
+
+    /// The snapshot's paths can hold secrets too. `hai undo` restores to the
+    /// paths kept in the trash.
+    fn redacted_snapshot(&self, snapshot: &Snapshot) -> Snapshot {
+        let redact_path =
+            |path: &PathBuf| PathBuf::from(self.redactor.redact(&path.to_string_lossy()));
+        Snapshot {
+            id: snapshot.id.clone(),
+            files: snapshot
+                .files
+                .iter()
+                .map(|file| SavedFile {
+                    original: redact_path(&file.original),
+                    stored: file.stored.clone(),
+                    moved_to: file.moved_to.as_ref().map(redact_path),
+                })
+                .collect(),
+        }
+    }
+
     pub fn load() -> Result<Self> {
+        Self::load_with(&crate::config::load_config()?)
+    }
+
+    /// Loads the history using settings from an already loaded config
+    pub fn load_with(config: &crate::config::Config) -> Result<Self> {
         let history_path = get_history_path()?;
-        let config = crate::config::load_config()?;
 
         if !history_path.exists() {
             // Use history_size from config
-            return Ok(Self::new(config.history_size()));
+            let mut history = Self::new(config.history_size());
+            history.redactor = config.redactor()?;
+            return Ok(history);
         }
 
         let history_str = fs::read_to_string(&history_path).map_err(|e| {
@@ -73,8 +199,9 @@ impl History {
             ))
         })?;
 
-        // Update max_size from config in case it changed
+        // Update max_size and redaction from config in case they changed
         history.max_size = config.history_size();
+        history.redactor = config.redactor()?;
 
         Ok(history)
     }
@@ -82,16 +209,22 @@ impl History {
     pub fn save(&self) -> Result<()> {
         let history_path = get_history_path()?;
 
-        let history_str = serde_json::to_string_pretty(self)
+        // Secrets typed into prompts or echoed back by the model must never
+        // reach the disk
+        let history_str = serde_json::to_string_pretty(&self.redacted())
             .map_err(|e| HaiError::serialization(format!("Failed to serialize history: {}", e)))?;
 
-        fs::write(&history_path, history_str).map_err(|e| {
+        utils::write_private_file(&history_path, &history_str).map_err(|e| {
             HaiError::io(format!(
                 "Failed to write history file to {:?}: {}",
                 history_path, e
             ))
         })?;
 
+        // Snapshots of entries that were trimmed, purged or restored are no
+        // longer reachable. Failing to clean them up is not worth an error.
+        let _ = trash::prune(self.entries.iter().filter_map(|e| e.snapshot.as_ref()));
+
         Ok(())
     }
 }
@@ -130,7 +263,7 @@ mod tests {
         assert_eq!(history.entries.len(), 1);
         assert_eq!(history.entries[0].prompt, "list files");
         assert_eq!(history.entries[0].command, "ls -la");
-        assert_eq!(history.entries[0].executed, true);
+        assert!(history.entries[0].executed);
         assert_eq!(history.entries[0].model, "gpt-4o-mini");
 
         // Add second entry
@@ -138,7 +271,7 @@ mod tests {
         assert_eq!(history.entries.len(), 2);
         assert_eq!(history.entries[1].prompt, "show processes");
         assert_eq!(history.entries[1].command, "ps aux");
-        assert_eq!(history.entries[1].executed, false);
+        assert!(!history.entries[1].executed);
         assert_eq!(history.entries[1].model, "claude-3");
 
         // Add third entry (should remove first entry due to max_size)
@@ -189,7 +322,7 @@ mod tests {
         assert_eq!(loaded_history.entries.len(), 1);
         assert_eq!(loaded_history.entries[0].prompt, "test prompt");
         assert_eq!(loaded_history.entries[0].command, "test command");
-        assert_eq!(loaded_history.entries[0].executed, true);
+        assert!(loaded_history.entries[0].executed);
         assert_eq!(loaded_history.entries[0].model, "test model");
     }
 
@@ -251,6 +384,113 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_purge_matching() {
+        let mut history = History::new(10);
+        history.add_entry("connect to db", "psql -h db.internal", true, "model1");
+        history.add_entry("list files", "ls -la", true, "model1");
+        history.add_entry("db backup", "pg_dump mydb", false, "model1");
+
+        let removed = history.purge_matching(&Regex::new("db").unwrap());
+        assert_eq!(removed, 2);
+        assert_eq!(history.entries.len(), 1);
+        assert_eq!(history.entries[0].prompt, "list files");
+
+        let removed = history.purge_matching(&Regex::new("nothing-matches").unwrap());
+        assert_eq!(removed, 0);
+        assert_eq!(history.entries.len(), 1);
+    }
+
+    #[test]
+    fn test_latest_with_snapshot() {
+        let mut history = History::new(10);
+        assert!(history.latest_with_snapshot().is_none());
+
+        let mut entry = HistoryEntry::new("clean up", "rm *.log", true, "model1");
+        entry.snapshot = Some(Snapshot {
+            id: "1".to_string(),
+            files: Vec::new(),
+        });
+        history.push(entry);
+        history.add_entry("list files", "ls", true, "model1");
+
+        let latest = history.latest_with_snapshot().unwrap();
+        assert_eq!(latest.command, "rm *.log");
+        latest.snapshot = None;
+        assert!(history.latest_with_snapshot().is_none());
+    }
+
+    #[test]
+    fn test_record_execution() {
+        let execution = Execution {
+            exit_code: Some(2),
+            duration: std::time::Duration::from_millis(1500),
+            cwd: Some(PathBuf::from("/tmp")),
+            stdout: None,
+            stderr: Some("error: oops\n".to_string()),
+        };
+
+        let mut entry = HistoryEntry::new("fail", "exit 2", false, "model1");
+        entry.record_execution(&execution, 1024);
+        assert!(entry.executed);
+        assert_eq!(entry.exit_code, Some(2));
+        assert_eq!(entry.duration_ms, Some(1500));
+        assert_eq!(entry.cwd, Some(PathBuf::from("/tmp")));
+        assert_eq!(entry.stderr.as_deref(), Some("error: oops\n"));
+
+        // Output is trimmed to the limit, or dropped without one
+        entry.record_execution(&execution, 5);
+        assert_eq!(entry.stderr.as_deref(), Some("oops\n"));
+        entry.record_execution(&execution, 0);
+        assert!(entry.stderr.is_none());
+
+        // Fields that weren't recorded are left out of the file
+        let json = serde_json::to_string(&entry).unwrap();
+        assert!(json.contains("\"exit_code\":2"));
+        assert!(!json.contains("stdout"));
+    }
+
+    #[test]
+    fn test_entries_without_snapshot_field() {
+        // History files written by older versions have no snapshot field
+        let json = r#"{"entries":[{"prompt":"p","command":"c","timestamp":"2025-03-14T00:00:00Z","executed":true,"model":"m"}],"max_size":5}"#;
+        let history: History = serde_json::from_str(json).unwrap();
+        assert!(history.entries[0].snapshot.is_none());
+    }
+
+    #[test]
+    fn test_redacted_copy() {
+        let mut history = History::new(10);
+        history.add_entry(
+            "use token sk-abcdefghijklmnopqrstuvwxyz",
+            "MY_TOKEN=hunter2 ./deploy.sh",
+            true,
+            "model1",
+        );
+        history.entries[0].stderr = Some("login failed for password=hunter2".to_string());
+        history.entries[0].snapshot = Some(Snapshot {
+            id: "1".to_string(),
+            files: vec![SavedFile {
+                original: PathBuf::from("/tmp/TOKEN=hunter2.txt"),
+                stored: "0".to_string(),
+                moved_to: Some(PathBuf::from("/tmp/PASSWORD=hunter2")),
+            }],
+        });
+
+        let serialized = serde_json::to_string(&history.redacted()).unwrap();
+        assert!(!serialized.contains("sk-abcdefghijklmnop"));
+        assert!(!serialized.contains("hunter2"));
+        assert!(serialized.contains("MY_TOKEN=[REDACTED]"));
+        assert!(serialized.contains("/tmp/TOKEN=[REDACTED]"));
+
+        // The in-memory history is left untouched
+        assert_eq!(history.entries[0].command, "MY_TOKEN=hunter2 ./deploy.sh");
+
+        history.redactor = Redactor::disabled();
+        let serialized = serde_json::to_string(&history.redacted()).unwrap();
+        assert!(serialized.contains("hunter2"));
+    }
+
     #[test]
     fn test_serialization_deserialization() {
         let mut history = History::new(10);
@@ -268,11 +508,11 @@ mod tests {
         assert_eq!(deserialized.entries.len(), 2);
         assert_eq!(deserialized.entries[0].prompt, "prompt1");
         assert_eq!(deserialized.entries[0].command, "command1");
-        assert_eq!(deserialized.entries[0].executed, true);
+        assert!(deserialized.entries[0].executed);
         assert_eq!(deserialized.entries[0].model, "model1");
         assert_eq!(deserialized.entries[1].prompt, "prompt2");
         assert_eq!(deserialized.entries[1].command, "command2");
-        assert_eq!(deserialized.entries[1].executed, false);
+        assert!(!deserialized.entries[1].executed);
         assert_eq!(deserialized.entries[1].model, "model2");
     }
 }
@@ -0,0 +1,178 @@
+use std::path::Path;
+
+use crate::shell_state::quote_for;
+
+/// Shells that `hai init` has integration for
+pub const SHELLS: [&str; 3] = ["bash", "zsh", "fish"];
+
+/// Ctrl-G replaces the command line with a suggestion for it. The prompt hook
+/// passes the last command and its status to `hai fix`, and repeats the
+/// directory and environment changes of commands hai ran. `{state_file}` is
+/// replaced with the start of the path of that shell's state file.
+const BASH_INIT: &str = r#"# hai shell integration for bash
+# Add to ~/.bashrc: eval "$(hai init bash)"
+
+_hai_widget() {
+    [[ -z $READLINE_LINE ]] && return
+    local suggestion
+    suggestion=$(hai --print -- "$READLINE_LINE" </dev/tty) || return
+    READLINE_LINE=$suggestion
+    READLINE_POINT=${#READLINE_LINE}
+}
+
+_hai_precmd() {
+    export HAI_LAST_STATUS=$?
+    export HAI_LAST_COMMAND="$(HISTTIMEFORMAT= builtin history 1 | sed 's/^ *[0-9]* *//')"
+    if [[ -f $HAI_STATE_FILE ]]; then
+        builtin source "$HAI_STATE_FILE"
+        command rm -f -- "$HAI_STATE_FILE"
+    fi
+}
+
+export HAI_STATE_FILE={state_file}$$
+
+bind -x '"\C-g": _hai_widget'
+PROMPT_COMMAND="_hai_precmd${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+"#;
+
+const ZSH_INIT: &str = r#"# hai shell integration for zsh
+# Add to ~/.zshrc: eval "$(hai init zsh)"
+
+_hai_widget() {
+    [[ -z $BUFFER ]] && return
+    local suggestion
+    suggestion=$(hai --print -- "$BUFFER" </dev/tty)
+    if [[ $? -eq 0 ]]; then
+        BUFFER=$suggestion
+        CURSOR=${#BUFFER}
+    fi
+    zle reset-prompt
+}
+
+_hai_preexec() {
+    _hai_command=$1
+}
+
+_hai_precmd() {
+    export HAI_LAST_STATUS=$? HAI_LAST_COMMAND=$_hai_command
+    if [[ -f $HAI_STATE_FILE ]]; then
+        builtin source "$HAI_STATE_FILE"
+        command rm -f -- "$HAI_STATE_FILE"
+    fi
+}
+
+export HAI_STATE_FILE={state_file}$$
+
+zle -N _hai_widget
+bindkey '^G' _hai_widget
+
+autoload -Uz add-zsh-hook
+add-zsh-hook preexec _hai_preexec
+add-zsh-hook precmd _hai_precmd
+"#;
+
+const FISH_INIT: &str = r#"# hai shell integration for fish
+# Add to ~/.config/fish/config.fish: hai init fish | source
+
+function _hai_widget
+    set -l line (commandline | string collect)
+    test -z "$line"; and return
+    set -l suggestion (hai --print -- "$line" </dev/tty | string collect)
+    and commandline --replace -- $suggestion
+    commandline --function repaint
+end
+
+function _hai_postexec --on-event fish_postexec
+    set -gx HAI_LAST_STATUS $status
+    set -gx HAI_LAST_COMMAND $argv[1]
+    if test -f "$HAI_STATE_FILE"
+        source $HAI_STATE_FILE
+        command rm -f -- $HAI_STATE_FILE
+    end
+end
+
+set -gx HAI_STATE_FILE {state_file}$fish_pid
+
+bind \cg _hai_widget
+"#;
+
+/// The integration script for a shell, or `None` if there is none. State
+/// files are kept in `state_dir`, one per shell process.
+pub fn script(shell: &str, state_dir: &Path) -> Option<String> {
+    let template = match shell {
+        "bash" => BASH_INIT,
+        "zsh" => ZSH_INIT,
+        "fish" => FISH_INIT,
+        _ => return None,
+    };
+
+    let state_file = state_dir.join("state-");
+    Some(template.replace(
+        "{state_file}",
+        &quote_for(&state_file.to_string_lossy(), shell),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cli, Commands};
+    use clap::Parser;
+
+    #[test]
+    fn test_scripts() {
+        let state_dir = Path::new("/home/me/.config/hai/state");
+        for shell in SHELLS {
+            let script = script(shell, state_dir).unwrap();
+            assert!(script.contains("hai --print -- "));
+            assert!(script.contains("HAI_LAST_COMMAND"));
+            assert!(script.contains("HAI_LAST_STATUS"));
+            assert!(
+                script.contains("HAI_STATE_FILE '/home/me/.config/hai/state/state-'$")
+                    || script.contains("HAI_STATE_FILE='/home/me/.config/hai/state/state-'$")
+            );
+        }
+        assert!(script("powershell", state_dir).is_none());
+    }
+
+    #[test]
+    fn test_scripts_parse() {
+        // Check the syntax with the shells that are installed
+        for shell in SHELLS {
+            let check = match shell {
+                "fish" => vec!["--no-execute", "-c"],
+                _ => vec!["-n", "-c"],
+            };
+            let result = std::process::Command::new(shell)
+                .args(check)
+                .arg(script(shell, Path::new("/tmp/it's")).unwrap())
+                .output();
+            if let Ok(output) = result {
+                assert!(
+                    output.status.success(),
+                    "{} rejected its script: {}",
+                    shell,
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_cli_init() {
+        let cli = Cli::try_parse_from(["hai", "init", "zsh"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Init { ref shell }) if shell == "zsh"));
+    }
+
+    #[test]
+    fn test_cli_print_after_separator() {
+        // The widgets pass the line after --, so a prompt that looks like a
+        // subcommand is still a prompt
+        let cli = Cli::try_parse_from(["hai", "--print", "--", "history | grep ssh"]).unwrap();
+        assert!(cli.print && cli.command.is_none());
+        assert_eq!(cli.prompt, "history | grep ssh");
+        let cli = Cli::try_parse_from(["hai", "--print", "--", "undo"]).unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(cli.prompt, "undo");
+    }
+}
@@ -1,26 +1,153 @@
 use anyhow::{Context, Result};
-use clap::Parser;
-use dialoguer::{theme::ColorfulTheme, Confirm};
-use std::io::{self, Read};
-use std::process::Command;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use dialoguer::{theme::ColorfulTheme, BasicHistory, Confirm, Editor, Input, Password, Select};
+use std::io::{self, BufRead, IsTerminal, Read};
+use std::path::{Path, PathBuf};
 
+mod attach;
+mod completions;
 mod config;
+mod config_edit;
+mod context;
 mod error;
+mod executor;
+mod explain;
+mod fix;
 mod history;
+mod init;
+mod man;
+mod pipe;
+mod plan;
+mod preview;
 mod providers;
+mod redact;
+mod repl;
+mod safety;
+mod sandbox;
+mod secrets;
+mod shell_state;
+mod tools;
+mod translate;
+mod trash;
 mod utils;
 
+use attach::Attachment;
 use config::{load_config, Config};
 use error::run_with_error_handling;
+use executor::{execute_command, Capture, Execution, ShellMode};
+use providers::Message;
+use repl::MetaCommand;
 
+/// A tiny CLI tool that turns natural language into shell commands
+///
+/// hai sends the prompt to the configured AI provider, along with the
+/// operating system and shell, and suggests a command that works there.
+/// OpenAI and Anthropic models are supported, as are Bash, Zsh, Fish and
+/// PowerShell.
+///
+/// After showing the suggestion, hai asks whether to Run it, Edit it, Refine
+/// it or Cancel. Single-line commands are edited in place; multi-line
+/// commands open in $EDITOR. The edited command is shown again for approval,
+/// and it is the edited version that runs and is saved to the history.
+///
+/// Refine asks what should change ("only in src/", "use fd instead") and
+/// sends it to the model along with the earlier request and suggestion, so
+/// the whole prompt doesn't have to be typed again. A suggestion can be
+/// refined any number of times.
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     /// The prompt to convert to a shell command
+    ///
+    /// Read from standard input if not given. If it is given, data piped to
+    /// hai is sent to the model along with it, so that the command fits the
+    /// data's format, e.g. `cat access.log | hai "count unique IPs"`. Large
+    /// data is cut down to a sample of its first and last lines.
     #[arg(default_value = "")]
     prompt: String,
 
+    /// Attach a file, so that the command fits the data in it
+    ///
+    /// A sample of the file is sent to the model along with the prompt, like
+    /// piped data. Can be given several times; all attachments share a limit
+    /// of 8 KB.
+    #[arg(
+        short = 'f',
+        long = "file",
+        value_name = "FILE",
+        conflicts_with = "interactive"
+    )]
+    files: Vec<PathBuf>,
+
+    #[command(flatten)]
+    run: RunArgs,
+
+    /// Ask for a list of commands and run them one step at a time
+    ///
+    /// The whole plan is shown first, then each step is shown and asks
+    /// whether to Run it, Edit it, Skip it, Run all remaining steps or
+    /// Cancel. Each step runs in its own shell from the current directory,
+    /// and the plan stops at the first step that fails. With --yes every step
+    /// runs without asking, except steps that need elevated privileges.
+    #[arg(short = 'P', long, conflicts_with = "sandbox")]
+    plan: bool,
+
+    /// Save the plan as an executable script
+    ///
+    /// The script stops at the first failing command. An existing FILE is
+    /// never overwritten. Combine with --no-execute to only write the script.
+    #[arg(long, value_name = "FILE", requires = "plan")]
+    save_script: Option<PathBuf>,
+
+    /// Keep a session open to make several requests in a row
+    ///
+    /// Each line typed is a request, reviewed and run like a single hai call.
+    /// The session remembers the conversation, so a request can refer to
+    /// earlier ones ("now only the ones from today"), and the arrow keys
+    /// recall earlier lines. Other options apply to every request. Lines
+    /// starting with / are session commands: /model [NAME] and /shell [NAME]
+    /// show or switch the model and shell, /explain [COMMAND] explains a
+    /// command (the last suggested one by default), /history shows the
+    /// history, /reset forgets the conversation, /help lists these and /exit
+    /// or Ctrl-D leaves.
+    #[arg(short = 'i', long, conflicts_with = "plan")]
+    interactive: bool,
+
+    /// Transform the data on stdin and write the result to stdout
+    ///
+    /// hai asks for a command that does what the prompt says with the data
+    /// piped to it, runs that command on the data without asking, and writes
+    /// its output to stdout, so that it can sit in the middle of a pipeline:
+    /// `kubectl get pods -o json | hai --pipe "names of crashing pods" |
+    /// xargs ...`. The model sees a sample of the data. The command is shown
+    /// on stderr. Only known read-only filters such as jq, awk, sed, grep and
+    /// sort are run, never with options or redirections that write files.
+    /// With --no-execute the command is printed instead.
+    #[arg(long, conflicts_with_all = ["plan", "interactive", "print"])]
+    pipe: bool,
+
+    /// Only print the suggested command, for shell integration
+    #[arg(long, conflicts_with_all = ["plan", "interactive"])]
+    print: bool,
+
+    /// Show command history
+    ///
+    /// Includes whether each command ran, its exit code, duration and working
+    /// directory.
+    #[arg(short = 'H', long = "history")]
+    show_history: bool,
+}
+
+/// How a suggested command is reviewed and run
+#[derive(Args, Debug)]
+struct RunArgs {
     /// Skip the prompt and just run the command
+    ///
+    /// Commands that use sudo, doas, su -c or pkexec still ask for
+    /// confirmation unless allow-sudo is set in hai-config(5).
     #[arg(short = 'y', long)]
     yes: bool,
 
@@ -28,13 +155,276 @@ struct Cli {
     #[arg(short = 'n', long = "no-execute")]
     no_execute: bool,
 
+    /// List the files the command would delete, move or overwrite
+    ///
+    /// Globs are expanded and find expressions are run with -delete replaced
+    /// by -print, so nothing is changed. Understands rm, mv, sed -i,
+    /// find -delete, find -exec rm and output redirections. Combine with
+    /// --no-execute to only see the impact.
+    #[arg(short = 'p', long)]
+    preview: bool,
+
+    /// Try the command in a sandbox and show what it changed before running it
+    ///
+    /// The sandbox uses bwrap(1) (bubblewrap, Linux only): the root file
+    /// system is read-only, there is no network, and the current directory is
+    /// an overlay whose changes are thrown away afterwards. Needs bubblewrap
+    /// 0.10 or newer. Never runs automatically with --yes.
+    #[arg(short = 's', long)]
+    sandbox: bool,
+
+    /// Describe what the suggested command will do before asking to run it
+    ///
+    /// The description is shown again after the command is edited. Also
+    /// enabled by explain in hai-config(5).
+    #[arg(short = 'x', long)]
+    explain: bool,
+
     /// Select the model to use
+    ///
+    /// Takes priority over HAI_DEFAULT_MODEL.
     #[arg(short = 'm', long)]
     model: Option<String>,
 
-    /// Show command history
-    #[arg(short = 'H', long = "history")]
-    show_history: bool,
+    /// How to start the shell that runs the command
+    ///
+    /// command runs a plain `shell -c`, interactive an interactive shell that
+    /// loads your rc file, with its aliases and functions, and login a login
+    /// shell that loads your profile. PowerShell always runs the same way.
+    /// Overrides shell-mode in hai-config(5).
+    #[arg(long, value_enum)]
+    shell_mode: Option<ShellMode>,
+
+    /// Tell the model about the current directory
+    ///
+    /// Sends the names of the files in it, the kind of project (from files
+    /// such as Cargo.toml, package.json or Makefile) with its make targets or
+    /// package scripts, and the git branch and number of changed files, so
+    /// that requests like "run the tests" get the project's own commands. The
+    /// summary is limited to 2 KB. Also enabled by context in hai-config(5).
+    #[arg(long)]
+    context: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Show or manage command history
+    History {
+        #[command(subcommand)]
+        action: Option<HistoryAction>,
+    },
+
+    /// Restore the files removed or overwritten by the last command
+    ///
+    /// Only commands that saved a snapshot to the trash can be undone (see
+    /// trash in hai-config(5)). Files that were moved are put back at their
+    /// original location and the moved copy is removed.
+    Undo {
+        /// Restore without asking for confirmation
+        #[arg(short = 'y', long)]
+        yes: bool,
+    },
+
+    /// Ask for a corrected version of the last command that failed
+    ///
+    /// The correction is reviewed like any other suggestion. Without COMMAND,
+    /// the last command is taken from HAI_LAST_COMMAND or else from the
+    /// shell's history file (bash and zsh may only write it when the shell
+    /// exits). Error output piped to hai fix is sent to the model as well,
+    /// e.g. `make 2>&1 | hai fix make`.
+    Fix {
+        /// The command to fix, instead of the last one in the shell history
+        command: Option<String>,
+
+        /// Exit code of the failed command
+        #[arg(short = 'e', long)]
+        exit_code: Option<i32>,
+
+        #[command(flatten)]
+        run: RunArgs,
+    },
+
+    /// Explain what each part of a command does
+    ///
+    /// Gives a one-sentence summary, then what each program, flag, argument,
+    /// pipe stage and redirection does. The command is never run.
+    Explain {
+        /// The command to explain, read from stdin if not given
+        command: Option<String>,
+    },
+
+    /// Print a script that binds Ctrl-G to replace the command line with a
+    /// suggestion
+    ///
+    /// Load it from your shell's startup file with `eval "$(hai init bash)"`,
+    /// `eval "$(hai init zsh)"` or `hai init fish | source`. Pressing Ctrl-G
+    /// then replaces the command line with the command suggested for it,
+    /// ready to edit and run in your shell, where it lands in your shell
+    /// history and has your aliases and functions. To use another key, bind
+    /// _hai_widget yourself.
+    ///
+    /// The script also exports HAI_LAST_COMMAND and HAI_LAST_STATUS before
+    /// each prompt for hai fix. Commands that hai runs from a shell with the
+    /// integration loaded change that shell too: if a command changes
+    /// directory or exports, changes or unsets environment variables, the
+    /// shell repeats those changes before its next prompt.
+    Init {
+        /// The shell to integrate with
+        #[arg(value_parser = init::SHELLS)]
+        shell: String,
+    },
+
+    /// Saves the working directory and environment to a file, for the
+    /// shell integration
+    #[command(name = "__save-state", hide = true)]
+    SaveState { path: PathBuf },
+
+    /// Translate a command or script to another shell
+    ///
+    /// The translation is printed, so that it can be redirected to a file,
+    /// e.g. `hai translate --to fish -f env.sh > env.fish`.
+    Translate {
+        /// The shell to translate to
+        #[arg(long, value_parser = translate::SHELLS)]
+        to: String,
+
+        /// The shell to translate from [default: the script's #! line, or
+        /// your shell]
+        #[arg(long, value_parser = translate::SHELLS)]
+        from: Option<String>,
+
+        /// Translate a script file
+        #[arg(short = 'f', long, value_name = "FILE", conflicts_with = "command")]
+        file: Option<PathBuf>,
+
+        /// The command to translate, read from stdin if neither it nor --file
+        /// is given
+        command: Option<String>,
+    },
+
+    /// Print a completion script for a shell
+    ///
+    /// Completes subcommands, options and their values. Bash, Zsh and Fish
+    /// also complete the names of the models in your configuration. Load it
+    /// from your shell's startup file, e.g. `source <(hai completions bash)`,
+    /// or save it where your shell looks for completions, e.g.
+    /// `hai completions fish > ~/.config/fish/completions/hai.fish`. Saved
+    /// scripts need to be generated again when models are added.
+    Completions {
+        /// The shell to complete for
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+
+    /// List the installed tools that suggestions may use
+    ///
+    /// hai looks for common tools on your PATH, such as rg, fd, jq, docker,
+    /// kubectl and package managers, and tells the model about them so that
+    /// it prefers tools you have. The list is saved and only scanned again
+    /// when PATH changes, or with --refresh. Disable it with detect-tools in
+    /// hai-config(5).
+    Tools {
+        /// Scan PATH again, e.g. after installing a tool
+        #[arg(short = 'r', long)]
+        refresh: bool,
+    },
+
+    /// Show or change settings without editing the config file by hand
+    ///
+    /// Keys are written like TOML dotted keys, e.g. temperature or
+    /// models.gpt-4o-mini.auth-token, with names that contain dots in quotes:
+    /// 'models."gpt-4.1".provider'. set and unset change your config file
+    /// and keep its comments, or the project's .hai.toml with --project.
+    /// get and list show the settings from all config files merged, as they
+    /// are used. See hai-config(5) for the settings.
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Writes hai(1) and a page for each subcommand to a directory
+    #[command(name = "__man", hide = true)]
+    Man { dir: PathBuf },
+}
+
+#[derive(Subcommand, Debug)]
+enum HistoryAction {
+    /// Remove entries whose prompt or command matches a regular expression
+    Purge {
+        /// Regular expression to match against prompts and commands
+        #[arg(long)]
+        matching: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Print the value of a setting, with API keys masked
+    Get {
+        key: String,
+        /// Print API keys in full
+        #[arg(long)]
+        show_secrets: bool,
+    },
+
+    /// Change a setting
+    ///
+    /// The value is read as TOML, so that numbers, true and false and lists
+    /// such as '["corp-[0-9]+"]' keep their type, and as a string otherwise.
+    /// Unknown settings and values of the wrong type are refused.
+    Set {
+        key: String,
+        value: String,
+        /// Change the project's .hai.toml, created in the current directory
+        /// if there is none. Only the settings a project may set are
+        /// accepted, and never API keys.
+        #[arg(long)]
+        project: bool,
+    },
+
+    /// Remove a setting, or a whole model such as models.gpt-4o-mini
+    Unset {
+        key: String,
+        /// Change the project's .hai.toml
+        #[arg(long)]
+        project: bool,
+    },
+
+    /// List the settings from all config files, with API keys masked
+    List {
+        /// Print API keys in full
+        #[arg(long)]
+        show_secrets: bool,
+    },
+
+    /// Print the path of your config file
+    Path {
+        /// List every config file that is read, in the order they are applied
+        #[arg(short = 'a', long)]
+        all: bool,
+    },
+
+    /// Open your config file in $VISUAL or $EDITOR, then check it
+    Edit {
+        /// Open the project's .hai.toml
+        #[arg(long)]
+        project: bool,
+    },
+
+    /// Check the config for unknown settings, a default model that isn't
+    /// configured, unknown providers and missing API keys
+    Validate,
+
+    /// Store a model's API key in the system keyring
+    ///
+    /// The key is read without showing it and stored in the Secret Service
+    /// (GNOME Keyring, KWallet) under the model's provider, so models of the
+    /// same provider share it. The model's auth-token in your config file is
+    /// replaced by auth-token-keyring = true. Linux only.
+    StoreToken {
+        /// The model whose key to store, as named in [models]
+        model: String,
+    },
 }
 
 fn get_prompt_from_stdin() -> Result<String> {
@@ -44,81 +434,635 @@ fn get_prompt_from_stdin() -> Result<String> {
 }
 
 async fn get_command_suggestion(prompt: &str, config: &Config) -> Result<String> {
+    ask_model(prompt, config.system_prompt(), config).await
+}
+
+async fn ask_model(prompt: &str, system_prompt: String, config: &Config) -> Result<String> {
+    converse(&[Message::user(prompt)], system_prompt, config).await
+}
+
+/// Sends a whole conversation, so that the model sees earlier suggestions
+/// and what the user asked to change about them
+async fn converse(messages: &[Message], system_prompt: String, config: &Config) -> Result<String> {
     // Get the provider name
-    let provider_name = std::env::var("HAI_DEFAULT_MODEL")
-        .ok()
-        .unwrap_or_else(|| config.default_model());
+    let provider_name = config.default_model();
 
     let provider = providers::create_provider(&provider_name, config)?;
-    provider
-        .get_command_suggestion(prompt, config.system_prompt())
-        .await
-}
-
-fn execute_command(command: &str, shell: &str) -> Result<()> {
-    // Run the command using the specified shell
-    let status = match shell {
-        "powershell" | "pwsh" => Command::new(shell)
-            .arg("-Command")
-            .arg(command)
-            .status()
-            .context("Failed to execute PowerShell command")?,
-        "fish" => Command::new(shell)
-            .arg("-c")
-            .arg(command)
-            .status()
-            .context("Failed to execute Fish command")?,
-        _ => Command::new(shell)
-            .arg("-c")
-            .arg(command)
-            .status()
-            .context("Failed to execute command")?,
+    provider.complete(messages, system_prompt).await
+}
+
+async fn explain_command(command: Option<String>, config: &Config) -> Result<()> {
+    let command = match command {
+        Some(command) => command,
+        None => get_prompt_from_stdin()?,
     };
+    if command.trim().is_empty() {
+        return Err(anyhow::anyhow!("No command provided"));
+    }
 
-    // If command failed, return an error
-    if !status.success() {
-        return Err(anyhow::anyhow!(
-            "Command '{}' failed with exit code: {}",
-            command,
-            status.code().unwrap_or(-1)
+    let config = config.with_min_max_tokens(explain::EXPLAIN_MAX_TOKENS);
+    let response = ask_model(command.trim(), explain::system_prompt(&config), &config).await?;
+
+    println!("$ {}", command.trim());
+    explain::print_explanation(&explain::parse_explanation(&response));
+    Ok(())
+}
+
+/// Prints a command or script translated to another shell, so that it can
+/// be redirected to a file
+async fn translate_command(
+    to: &str,
+    from: Option<String>,
+    file: Option<&Path>,
+    command: Option<String>,
+) -> Result<()> {
+    let input = match (file, command) {
+        (Some(path), _) => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?,
+        (None, Some(command)) => command,
+        (None, None) => get_prompt_from_stdin()?,
+    };
+    if input.trim().is_empty() {
+        return Err(anyhow::anyhow!("Nothing to translate"));
+    }
+
+    let config = load_config()?;
+    let from = from
+        .or_else(|| translate::detect_shell(&input).map(str::to_string))
+        .unwrap_or_else(|| config.shell());
+
+    let config = config.with_min_max_tokens(translate::max_tokens(&input));
+    let system_prompt = translate::system_prompt(&config, &from, to);
+    let response = ask_model(&input, system_prompt, &config).await?;
+
+    println!("{}", translate::strip_code_fence(&response));
+    Ok(())
+}
+
+fn show_history() -> Result<()> {
+    let history = history::History::load()?;
+    println!("Command History:");
+    println!("---------------");
+    for (i, entry) in history.get_entries().iter().enumerate() {
+        println!("{}: '{}'", i + 1, entry.prompt);
+        println!("   $ {}", entry.command);
+        println!(
+            "   [{}] [Model: {}] [{}]",
+            entry.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            entry.model,
+            execution_summary(entry)
+        );
+        if let Some(cwd) = &entry.cwd {
+            println!("   [Directory: {}]", cwd.display());
+        }
+        for (label, output) in [("stdout", &entry.stdout), ("stderr", &entry.stderr)] {
+            if let Some(output) = output.as_deref().filter(|o| !o.trim().is_empty()) {
+                println!("   {}:", label);
+                for line in output.lines() {
+                    println!("     {}", line);
+                }
+            }
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn execution_summary(entry: &history::HistoryEntry) -> String {
+    if !entry.executed {
+        return "Not executed".to_string();
+    }
+
+    let mut summary = match entry.exit_code {
+        Some(code) => format!("Executed, exit code {}", code),
+        None => "Executed, killed by a signal".to_string(),
+    };
+    if let Some(duration_ms) = entry.duration_ms {
+        summary.push_str(&format!(", {:.1}s", duration_ms as f64 / 1000.0));
+    }
+    summary
+}
+
+fn purge_history(pattern: &str) -> Result<()> {
+    let pattern =
+        regex::Regex::new(pattern).with_context(|| format!("Invalid pattern '{}'", pattern))?;
+
+    let mut history = history::History::load()?;
+    let removed = history.purge_matching(&pattern);
+    history.save()?;
+
+    println!(
+        "Removed {} history {}",
+        removed,
+        if removed == 1 { "entry" } else { "entries" }
+    );
+    Ok(())
+}
+
+fn config_command(action: ConfigAction) -> Result<()> {
+    match action {
+        ConfigAction::Get { key, show_secrets } => {
+            let settings = config::merge_config_files(&config::config_files()?)?;
+            match config_edit::get(&settings, &config_edit::parse_key(&key)?) {
+                Some(toml::Value::String(value))
+                    if config_edit::is_secret(&key) && !show_secrets =>
+                {
+                    println!("{}", config_edit::mask(value))
+                }
+                Some(toml::Value::String(value)) => println!("{}", value),
+                Some(toml::Value::Table(table)) => {
+                    for line in config_edit::list(table, &key, show_secrets) {
+                        println!("{}", line);
+                    }
+                }
+                Some(value) => println!("{}", value),
+                None => return Err(error::HaiError::config(format!("{} is not set", key))),
+            }
+        }
+        ConfigAction::Set {
+            key,
+            value,
+            project,
+        } => {
+            if project {
+                config_edit::check_project_setting(&key)?;
+            }
+            let path = editable_config_path(project)?;
+            let mut doc = config_edit::load(&path)?;
+            config_edit::set(&mut doc, &key, &value)?;
+            save_config_file(&path, &doc.to_string(), project)?;
+        }
+        ConfigAction::Unset { key, project } => {
+            let path = editable_config_path(project)?;
+            let mut doc = config_edit::load(&path)?;
+            if config_edit::unset(&mut doc, &key)? {
+                save_config_file(&path, &doc.to_string(), project)?;
+            } else {
+                println!("{} is not set in {}", key, path.display());
+            }
+        }
+        ConfigAction::List { show_secrets } => {
+            let settings = config::merge_config_files(&config::config_files()?)?;
+            for line in config_edit::list(&settings, "", show_secrets) {
+                println!("{}", line);
+            }
+        }
+        ConfigAction::Path { all: false } => println!("{}", config::user_config_path()?.display()),
+        ConfigAction::Path { all: true } => {
+            for (layer, path) in config::config_files()? {
+                println!("{:<8} {}", layer.name(), path.display());
+            }
+        }
+        ConfigAction::Edit { project } => {
+            let path = editable_config_path(project)?;
+            open_in_editor(&path)?;
+            return validate_config();
+        }
+        ConfigAction::Validate => return validate_config(),
+        ConfigAction::StoreToken { model } => return store_token(&model),
+    }
+    Ok(())
+}
+
+fn store_token(model: &str) -> Result<()> {
+    if !secrets::KEYRING_SUPPORTED {
+        return Err(error::HaiError::config(
+            "Storing API keys in the keyring is only supported on Linux",
         ));
     }
 
+    let config = load_config()?;
+    let provider = config
+        .models()
+        .and_then(|models| models.get(model))
+        .map(|model_config| model_config.provider.clone())
+        .ok_or_else(|| error::HaiError::config(format!("Model '{}' not found in config", model)))?;
+
+    let token = Password::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("{} API key", provider))
+        .interact()?;
+    secrets::store_in_keyring(&provider, token.trim())?;
+
+    let path = editable_config_path(false)?;
+    let mut doc = config_edit::load(&path)?;
+    let key = format!("models.{}", config_edit::format_key(model));
+    config_edit::set(&mut doc, &format!("{}.auth-token-keyring", key), "true")?;
+    config_edit::unset(&mut doc, &format!("{}.auth-token", key))?;
+    save_config_file(&path, &doc.to_string(), false)?;
+
+    println!(
+        "Stored the {} API key in the keyring and removed it from {}",
+        provider,
+        path.display()
+    );
     Ok(())
 }
 
+/// The file that `hai config` changes: the user's config file, created with
+/// the defaults if needed, or with `--project` the project's .hai.toml
+fn editable_config_path(project: bool) -> Result<PathBuf> {
+    if project {
+        let cwd = std::env::current_dir()?;
+        return Ok(config::find_project_config(&cwd)
+            .unwrap_or_else(|| cwd.join(config::PROJECT_CONFIG_NAME)));
+    }
+
+    let path = config::user_config_path()?;
+    if std::env::var_os("HAI_CONFIG").is_none() {
+        utils::create_default_config_if_not_exists()?;
+    }
+    Ok(path)
+}
+
+/// Writes a config file. The user's may hold API keys, while a project's is
+/// meant to be shared.
+fn save_config_file(path: &Path, contents: &str, project: bool) -> Result<()> {
+    if project {
+        std::fs::write(path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    } else {
+        utils::write_private_file(path, contents)
+    }
+}
+
+fn open_in_editor(path: &Path) -> Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+    // Editors are often set with arguments, such as `code --wait`
+    let mut words = editor.split_whitespace();
+    let program = words.next().context("$EDITOR is empty")?;
+
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()
+        .with_context(|| format!("Failed to start {}", program))?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("{} exited with {}", program, status));
+    }
+    Ok(())
+}
+
+fn validate_config() -> Result<()> {
+    let files = config::config_files()?;
+    let settings = config::merge_config_files(&files)?;
+    let mut problems: Vec<String> = config::unknown_settings(&settings)
+        .into_iter()
+        .map(|key| format!("{}: unknown setting", key))
+        .collect();
+    problems.extend(config::from_settings(settings)?.problems());
+
+    if problems.is_empty() {
+        let paths: Vec<String> = files
+            .iter()
+            .map(|(_, path)| path.display().to_string())
+            .collect();
+        println!("No problems found in {}", paths.join(", "));
+        return Ok(());
+    }
+
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    Err(error::HaiError::config(format!(
+        "Found {} {} in the configuration",
+        problems.len(),
+        if problems.len() == 1 {
+            "problem"
+        } else {
+            "problems"
+        }
+    )))
+}
+
+/// Lists the files a command would touch, for shells whose syntax the
+/// preview understands
+fn show_preview(command: &str, shell: &str) {
+    if matches!(shell, "powershell" | "pwsh") {
+        return;
+    }
+
+    if let Ok(cwd) = std::env::current_dir() {
+        preview::print_preview(&preview::affected_files(command, &cwd));
+    }
+}
+
+/// Runs a confirmed command. If the trash is enabled, the files it would
+/// remove or overwrite are saved first so that `hai undo` can bring them back.
+fn run_confirmed(command: &str, config: &Config) -> (Option<trash::Snapshot>, Result<Execution>) {
+    let shell = config.shell();
+    let mut snapshot = None;
+
+    if config.trash() && !matches!(shell.as_str(), "powershell" | "pwsh") {
+        if let Ok(cwd) = std::env::current_dir() {
+            match trash::snapshot(&preview::affected_files(command, &cwd), &cwd) {
+                Ok(saved) => snapshot = saved,
+                Err(e) => eprintln!("Warning: {:#}. This command can't be undone.", e),
+            }
+        }
+    }
+
+    // With the shell integration, the user's shell repeats what the command
+    // changed about its directory and environment
+    let execution = match std::env::var_os("HAI_STATE_FILE") {
+        Some(state_file) => execute_keeping_state(command, &shell, config, Path::new(&state_file)),
+        None => execute_command(command, &shell, config.shell_mode(), output_capture(config)),
+    };
+
+    (snapshot, execution)
+}
+
+/// Runs a command wrapped so that its shell saves the directory and
+/// environment before and after it, and adds the difference to the state
+/// file the shell hook from `hai init` sources
+fn execute_keeping_state(
+    command: &str,
+    shell: &str,
+    config: &Config,
+    state_file: &Path,
+) -> Result<Execution> {
+    let mode = config.shell_mode();
+    let capture = output_capture(config);
+
+    let scratch = tempfile::tempdir().context("Failed to create a temporary directory")?;
+    let before = scratch.path().join("before");
+    let after = scratch.path().join("after");
+    let wrapped = std::env::current_exe()
+        .ok()
+        .and_then(|hai| shell_state::wrap(command, shell, &hai, &before, &after));
+    let Some(wrapped) = wrapped else {
+        return execute_command(command, shell, mode, capture);
+    };
+
+    let execution = execute_command(&wrapped, shell, mode, capture)?;
+
+    // Nothing is saved after a command that exits the shell itself
+    if let (Ok(before), Ok(after)) = (shell_state::load(&before), shell_state::load(&after)) {
+        let changes = shell_state::changes(&before, &after);
+        if !changes.is_empty() {
+            let script = shell_state::script(&changes, shell);
+            if let Err(e) = shell_state::append_script(state_file, &script) {
+                eprintln!("Warning: {:#}", e);
+            }
+        }
+    }
+
+    Ok(execution)
+}
+
+/// Output is only captured for the history, since a captured stream no
+/// longer goes to the terminal. The errors of a failed command are collected
+/// separately when the user asks for a fix, see [`error_output`].
+fn output_capture(config: &Config) -> Capture {
+    let size = config.history_output_size();
+    Capture {
+        stdout: size,
+        stderr: size,
+    }
+}
+
+fn undo(yes: bool) -> Result<()> {
+    let mut history = history::History::load()?;
+
+    let Some(entry) = history.latest_with_snapshot() else {
+        println!("Nothing to undo");
+        return Ok(());
+    };
+    let snapshot = trash::unredacted(entry.snapshot.as_ref().unwrap());
+
+    println!("$ {}", entry.command);
+    println!("Files to restore:");
+    for file in &snapshot.files {
+        println!("  {}", file.original.display());
+    }
+    let moved: Vec<&PathBuf> = snapshot
+        .files
+        .iter()
+        .filter_map(|f| f.moved_to.as_ref())
+        .collect();
+    if !moved.is_empty() {
+        println!("Moved copies to remove:");
+        for path in moved {
+            println!("  {}", path.display());
+        }
+    }
+
+    if !yes {
+        let confirmation = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Restore these files?")
+            .default(true)
+            .interact()?;
+
+        if !confirmation {
+            return Ok(());
+        }
+    }
+
+    trash::restore(&snapshot)?;
+
+    // Saving prunes the snapshot from the trash now that it has been used
+    entry.snapshot = None;
+    history.save()?;
+
+    println!("Restored {} files", snapshot.files.len());
+    Ok(())
+}
+
+/// What to do with a suggested command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Choice {
+    Run,
+    Edit,
+    Refine,
+    Cancel,
+}
+
+/// What to do with one step of a plan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StepChoice {
+    Run,
+    RunAll,
+    Skip,
+    Cancel,
+}
+
+/// Asks whether to run a step of a plan, letting the user edit it first
+fn review_step(step: &mut String) -> Result<StepChoice> {
+    let choices = [
+        Some(StepChoice::Run),
+        None,
+        Some(StepChoice::Skip),
+        Some(StepChoice::RunAll),
+        Some(StepChoice::Cancel),
+    ];
+
+    loop {
+        let selection = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Run this step?")
+            .default(0)
+            .items(&["Run", "Edit", "Skip", "Run all remaining steps", "Cancel"])
+            .interact_opt()?;
+
+        // Escape or q cancels
+        let Some(i) = selection else {
+            return Ok(StepChoice::Cancel);
+        };
+
+        match choices[i] {
+            Some(choice) => return Ok(choice),
+            None => {
+                if let Some(edited) = edit_command(step)? {
+                    *step = edited;
+                    println!("$ {}", step);
+
+                    if let Some(tool) = safety::privilege_escalation(step) {
+                        warn_privileged(tool);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn ask_to_run(sandboxed: bool) -> Result<Choice> {
+    let choices = [Choice::Run, Choice::Edit, Choice::Refine, Choice::Cancel];
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(if sandboxed {
+            "Run this command for real?"
+        } else {
+            "Run this command?"
+        })
+        .default(0)
+        .items(&["Run", "Edit", "Refine", "Cancel"])
+        .interact_opt()?;
+
+    // Escape or q cancels
+    Ok(selection.map_or(Choice::Cancel, |i| choices[i]))
+}
+
+/// Asks what to change about a suggestion, e.g. "only in src/". Returns
+/// `None` if nothing was entered.
+fn ask_refinement() -> Result<Option<String>> {
+    let refinement = Input::<String>::with_theme(&ColorfulTheme::default())
+        .with_prompt("What should change?")
+        .allow_empty(true)
+        .interact_text()?;
+
+    Ok(Some(refinement.trim().to_string()).filter(|r| !r.is_empty()))
+}
+
+/// Lets the user change a command before it runs. Single-line commands are
+/// edited inline, longer ones in $EDITOR. Returns `None` if the result is
+/// empty or the editor was closed without saving.
+fn edit_command(command: &str) -> Result<Option<String>> {
+    let edited = if command.contains('\n') {
+        Editor::new().extension(".sh").edit(command)?
+    } else {
+        Some(
+            Input::<String>::with_theme(&ColorfulTheme::default())
+                .with_prompt("$")
+                .with_initial_text(command)
+                .allow_empty(true)
+                .interact_text()?,
+        )
+    };
+
+    Ok(edited
+        .map(|edited| edited.trim().to_string())
+        .filter(|edited| !edited.is_empty()))
+}
+
+fn warn_privileged(tool: &str) {
+    eprintln!(
+        "Warning: this command uses {} and will run with elevated privileges.",
+        tool
+    );
+}
+
 async fn run() -> Result<()> {
     let cli = Cli::parse();
 
+    match cli.command {
+        Some(Commands::History { action: None }) => return show_history(),
+        Some(Commands::History {
+            action: Some(HistoryAction::Purge { matching }),
+        }) => return purge_history(&matching),
+        Some(Commands::Undo { yes }) => return undo(yes),
+        Some(Commands::Fix {
+            command,
+            exit_code,
+            run,
+        }) => return fix_command(command, exit_code, &run).await,
+        Some(Commands::Explain { command }) => {
+            return explain_command(command, &load_config()?).await
+        }
+        Some(Commands::Init { shell }) => {
+            let state_dir = utils::ensure_config_dir()?.join("state");
+            std::fs::create_dir_all(&state_dir).context("Failed to create state directory")?;
+            print!("{}", init::script(&shell, &state_dir).unwrap_or_default());
+            return Ok(());
+        }
+        Some(Commands::SaveState { path }) => {
+            return shell_state::save(&shell_state::capture()?, &path)
+        }
+        Some(Commands::Translate {
+            to,
+            from,
+            file,
+            command,
+        }) => return translate_command(&to, from, file.as_deref(), command).await,
+        Some(Commands::Completions { shell }) => {
+            let models = config::model_names();
+            print!("{}", completions::script(shell, Cli::command(), &models));
+            return Ok(());
+        }
+        Some(Commands::Tools { refresh }) => {
+            let tools = if refresh {
+                tools::refresh()?
+            } else {
+                tools::installed()
+            };
+            for tool in tools {
+                println!("{}", tool);
+            }
+            return Ok(());
+        }
+        Some(Commands::Config { action }) => return config_command(action),
+        Some(Commands::Man { dir }) => return man::write_pages(Cli::command(), &dir),
+        None => {}
+    }
+
     // If showing history, do that first and exit
     if cli.show_history {
-        let history = history::History::load()?;
-        println!("Command History:");
-        println!("---------------");
-        for (i, entry) in history.get_entries().iter().enumerate() {
-            println!("{}: '{}'", i + 1, entry.prompt);
-            println!("   $ {}", entry.command);
-            println!(
-                "   [{}] [Model: {}] [{}]",
-                entry.timestamp.format("%Y-%m-%d %H:%M:%S"),
-                entry.model,
-                if entry.executed {
-                    "Executed"
-                } else {
-                    "Not executed"
-                }
-            );
-            println!();
-        }
-        return Ok(());
+        return show_history();
+    }
+
+    if cli.interactive {
+        return run_interactive(&cli.run, load_config_for(&cli.run)?).await;
     }
 
-    // Get the input prompt either from CLI arguments or stdin
+    if cli.pipe {
+        return run_pipe(&cli.prompt, &cli.files, &cli.run).await;
+    }
+
+    // Get the input prompt either from CLI arguments or stdin. With a prompt
+    // argument, piped data is attached to it instead.
+    let mut attachments = Vec::new();
     let prompt = if cli.prompt.is_empty() {
         get_prompt_from_stdin()?
     } else {
+        if !io::stdin().is_terminal() {
+            let data = get_prompt_from_stdin()?;
+            if !data.trim().is_empty() {
+                attachments.push(Attachment::stdin(data));
+            }
+        }
         cli.prompt.clone()
     };
+    for path in &cli.files {
+        attachments.push(Attachment::file(path)?);
+    }
 
     // If we have no prompt, just exit
     if prompt.trim().is_empty() {
@@ -126,54 +1070,631 @@ async fn run() -> Result<()> {
     }
 
     // Load the config
-    let config = load_config()?;
+    let config = load_config_for(&cli.run)?;
+
+    // The model sees the attachments, the history only the prompt
+    let request = attach::request(&prompt, &attachments);
+
+    // The shell widget puts the command on the command line itself
+    if cli.print {
+        let command = get_command_suggestion(&request, &config).await?;
+        let outcome = Outcome {
+            command: command.trim().to_string(),
+            conversation: Vec::new(),
+            snapshot: None,
+            execution: None,
+        };
+        record_in_history(&prompt, &outcome, &config.default_model(), &config)?;
+
+        println!("{}", outcome.command);
+        return Ok(());
+    }
+
+    if cli.plan {
+        return run_plan(
+            &prompt,
+            &request,
+            &cli.run,
+            cli.save_script.as_deref(),
+            &config,
+        )
+        .await;
+    }
+
+    suggest_and_run(
+        Some(&prompt),
+        vec![Message::user(&request)],
+        &cli.run,
+        &config,
+    )
+    .await?
+    .into_result()
+}
+
+/// Loads the config with the model picked by --model, which takes priority
+/// over HAI_DEFAULT_MODEL and the default-model setting, and the shell mode
+/// picked by --shell-mode
+fn load_config_for(args: &RunArgs) -> Result<Config> {
+    let mut config = load_config()?;
+    if let Some(model) = &args.model {
+        config = config.with_model(model);
+    }
+    if let Some(mode) = args.shell_mode {
+        config = config.with_shell_mode(mode);
+    }
+    if args.context {
+        config = config.with_context_enabled();
+    }
+    Ok(config)
+}
+
+/// Runs `hai --interactive`: a session that keeps suggesting and running
+/// commands, remembering the conversation so that requests can build on
+/// earlier ones
+async fn run_interactive(args: &RunArgs, mut config: Config) -> Result<()> {
+    println!("Type a request, /help for commands, or /exit to leave.");
+
+    let mut input_history = BasicHistory::new().max_entries(100).no_duplicates(true);
+    let mut memory: Vec<Message> = Vec::new();
+    let mut last_command: Option<String> = None;
+
+    // Ctrl-D or a closed terminal ends the session
+    while let Ok(line) = Input::<String>::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("hai ({})", config.default_model()))
+        .history_with(&mut input_history)
+        .interact_text()
+    {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(meta) = repl::parse_meta_command(line) {
+            let meta = match meta {
+                Ok(meta) => meta,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+            };
+
+            let result = match meta {
+                MetaCommand::Exit => break,
+                MetaCommand::Help => {
+                    repl::print_help();
+                    Ok(())
+                }
+                MetaCommand::Model(None) => {
+                    let mut names: Vec<&String> = config
+                        .models()
+                        .map(|m| m.keys().collect())
+                        .unwrap_or_default();
+                    names.sort();
+                    println!("Model: {}", config.default_model());
+                    for name in names {
+                        println!("  {}", name);
+                    }
+                    Ok(())
+                }
+                MetaCommand::Model(Some(model)) => {
+                    if config.models().is_some_and(|m| m.contains_key(&model)) {
+                        config = config.with_model(&model);
+                        Ok(())
+                    } else {
+                        Err(anyhow::anyhow!("Model '{}' not found in config", model))
+                    }
+                }
+                MetaCommand::Shell(None) => {
+                    println!("Shell: {}", config.shell());
+                    Ok(())
+                }
+                MetaCommand::Shell(Some(shell)) => {
+                    config = config.with_shell(&shell);
+                    Ok(())
+                }
+                MetaCommand::Explain(command) => match command.or(last_command.clone()) {
+                    Some(command) => explain_command(Some(command), &config).await,
+                    None => Err(anyhow::anyhow!("No command to explain yet")),
+                },
+                MetaCommand::History => show_history(),
+                MetaCommand::Reset => {
+                    memory.clear();
+                    println!("Forgot the conversation");
+                    Ok(())
+                }
+            };
+
+            if let Err(e) = result {
+                eprintln!("Error: {:#}", e);
+            }
+            continue;
+        }
+
+        let mut conversation = memory.clone();
+        conversation.push(Message::user(line));
+
+        match suggest_and_run(Some(line), conversation, args, &config).await {
+            Ok(outcome) => {
+                memory = outcome.conversation.clone();
+                memory.push(Message::assistant(outcome.command.as_str()));
+                repl::trim_memory(&mut memory);
+                last_command = Some(outcome.command.clone());
+
+                if let Err(e) = outcome.into_result() {
+                    eprintln!("Error: {:#}", e);
+                }
+            }
+            Err(e) => eprintln!("Error: {:#}", e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs `hai --pipe`: asks for a command that turns the data on stdin into
+/// what the prompt asks for, then runs it on that data without asking
+async fn run_pipe(prompt: &str, files: &[PathBuf], args: &RunArgs) -> Result<()> {
+    if prompt.trim().is_empty() {
+        return Err(anyhow::anyhow!(
+            "--pipe needs a prompt argument, as the data is read from stdin"
+        ));
+    }
+
+    let mut input = Vec::new();
+    io::stdin()
+        .read_to_end(&mut input)
+        .context("Failed to read standard input")?;
+    let mut attachments = vec![Attachment::stdin(
+        String::from_utf8_lossy(&input).into_owned(),
+    )];
+    for path in files {
+        attachments.push(Attachment::file(path)?);
+    }
+
+    let config = load_config_for(args)?;
+    let request = attach::request(prompt, &attachments);
+    let response = ask_model(&request, pipe::system_prompt(&config), &config).await?;
+    let command = response.trim().to_string();
+
+    if args.no_execute {
+        println!("{}", command);
+        let outcome = Outcome {
+            command,
+            conversation: Vec::new(),
+            snapshot: None,
+            execution: None,
+        };
+        return record_in_history(prompt, &outcome, &config.default_model(), &config);
+    }
+
+    pipe::check(&command, &std::env::current_dir()?)?;
+
+    // stdout is the pipeline's, so the command is shown on stderr
+    eprintln!("$ {}", command);
+    let execution = executor::execute_filter(&command, &config.shell(), input);
+
+    let outcome = Outcome {
+        command,
+        conversation: Vec::new(),
+        snapshot: None,
+        execution: Some(execution),
+    };
+    record_in_history(prompt, &outcome, &config.default_model(), &config)?;
+    outcome.into_result()
+}
+
+/// Asks the model for several commands and runs them in order, each after
+/// its own confirmation, stopping at the first one that fails
+async fn run_plan(
+    prompt: &str,
+    request: &str,
+    args: &RunArgs,
+    save_script: Option<&Path>,
+    config: &Config,
+) -> Result<()> {
+    let model_name = config.default_model();
+    let plan_config = config.with_min_max_tokens(plan::PLAN_MAX_TOKENS);
+    let response = ask_model(request, plan::system_prompt(&plan_config), &plan_config).await?;
+
+    let mut steps = plan::parse_plan(&response, &config.shell());
+    if steps.is_empty() {
+        return Err(anyhow::anyhow!("The model did not suggest any commands"));
+    }
+
+    println!("Plan:");
+    for (i, step) in steps.iter().enumerate() {
+        println!("{:>3}. {}", i + 1, step);
+    }
+
+    if let Some(path) = save_script {
+        plan::save_script(path, &steps, prompt, &config.shell())?;
+        println!("Saved the plan to {}", path.display());
+    }
+
+    if args.no_execute {
+        return Ok(());
+    }
+
+    let preview = args.preview || config.preview();
+    let total = steps.len();
+    let mut run_all = args.yes;
+
+    for (i, step) in steps.iter_mut().enumerate() {
+        println!();
+        println!("Step {}/{}:", i + 1, total);
+        println!("$ {}", step);
+
+        let escalation = safety::privilege_escalation(step);
+        if let Some(tool) = escalation {
+            warn_privileged(tool);
+        }
+
+        if preview {
+            show_preview(step, &config.shell());
+        }
+
+        // Privileged steps are confirmed even when running everything
+        if !run_all || (escalation.is_some() && !config.allow_sudo()) {
+            match review_step(step)? {
+                StepChoice::Run => {}
+                StepChoice::RunAll => run_all = true,
+                StepChoice::Skip => continue,
+                StepChoice::Cancel => return Ok(()),
+            }
+        }
+
+        let (snapshot, result) = run_confirmed(step, config);
+        let outcome = Outcome {
+            command: step.clone(),
+            conversation: Vec::new(),
+            snapshot,
+            execution: Some(result),
+        };
+        record_in_history(prompt, &outcome, &model_name, config)?;
+
+        if let Some(result) = outcome.execution {
+            let execution = result?;
+            if !execution.success() {
+                eprintln!("Step {} failed, skipping the rest of the plan", i + 1);
+                return execution.check(step);
+            }
+        }
+    }
+
+    Ok(())
+}
 
-    // Get the model to use, prioritizing:
-    // 1. CLI --model flag
-    // 2. HAI_DEFAULT_MODEL env var
-    // 3. config file default-model field
-    let model_name = cli
-        .model
-        .or_else(|| std::env::var("HAI_DEFAULT_MODEL").ok())
-        .unwrap_or_else(|| config.default_model());
+/// Runs `hai fix`: asks the model to correct a command that failed, given
+/// its exit code and any error output piped to hai
+async fn fix_command(
+    command: Option<String>,
+    exit_code: Option<i32>,
+    args: &RunArgs,
+) -> Result<()> {
+    let config = load_config_for(args)?;
+
+    let command = match command {
+        Some(command) => command,
+        None => fix::last_shell_command(&config.shell())?,
+    };
+    let exit_code = exit_code.or_else(fix::last_exit_code);
+
+    // e.g. `make 2>&1 | hai fix`
+    let stderr = if io::stdin().is_terminal() {
+        String::new()
+    } else {
+        get_prompt_from_stdin()?
+    };
+    let stderr = tail(&stderr, fix::FIX_STDERR_BYTES);
+
+    println!("Fixing:");
+    println!("$ {}", command);
+
+    // The history records what was fixed rather than the whole prompt with
+    // the error output
+    let goal = format!("fix: {}", command);
+    let prompt = fix::fix_prompt(None, &command, exit_code, stderr);
+    suggest_and_run(Some(&goal), vec![Message::user(prompt)], args, &config)
+        .await?
+        .into_result()
+}
+
+/// The last `max_bytes` of `text`, cut at a character boundary
+fn tail(text: &str, max_bytes: usize) -> &str {
+    let mut start = text.len().saturating_sub(max_bytes);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    &text[start..]
+}
+
+/// Asks the model for a command, lets the user review and run it, and offers
+/// to fix it if it fails. The conversation ends with the request; `goal` is
+/// what the user asked for, if known, and is what the history records.
+async fn suggest_and_run(
+    goal: Option<&str>,
+    conversation: Vec<Message>,
+    args: &RunArgs,
+    config: &Config,
+) -> Result<Outcome> {
+    let model_name = config.default_model();
+    let prompt = conversation
+        .last()
+        .map(|message| message.content.clone())
+        .unwrap_or_default();
+    let history_prompt = goal.unwrap_or(&prompt);
 
     // Get a command suggestion
-    let command = get_command_suggestion(&prompt, &config).await?;
+    let command = converse(&conversation, config.system_prompt(), config).await?;
+    let mut outcome = review_and_run(command, conversation, args, config).await?;
+    record_in_history(history_prompt, &outcome, &model_name, config)?;
+
+    // Offer to have the model correct a command that failed
+    let mut attempts = 0;
+    while attempts < config.fix_attempts() && !args.yes {
+        let Some(Ok(execution)) = &outcome.execution else {
+            break;
+        };
+        if execution.success() {
+            break;
+        }
+        attempts += 1;
+
+        let ask_fix = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("The command failed. Ask for a fix?")
+            .default(true)
+            .interact()?;
+        if !ask_fix {
+            break;
+        }
+
+        let stderr = error_output(execution)?;
+        let fix_prompt = fix::fix_prompt(goal, &outcome.command, execution.exit_code, &stderr);
+        // The fix continues the conversation, so that an interactive
+        // session keeps what was said before it
+        let mut conversation = outcome.conversation.clone();
+        conversation.push(Message::assistant(outcome.command.as_str()));
+        conversation.push(Message::user(fix_prompt));
+        let command = converse(&conversation, config.system_prompt(), config).await?;
+        outcome = review_and_run(command, conversation, args, config).await?;
+        record_in_history(history_prompt, &outcome, &model_name, config)?;
+    }
+
+    Ok(outcome)
+}
+
+/// The end of a failed command's standard error, for the fix prompt. Unless
+/// history-output-size is set it went straight to the terminal, so the user
+/// can paste it or go without.
+fn error_output(execution: &Execution) -> Result<String> {
+    if let Some(stderr) = &execution.stderr {
+        return Ok(stderr.clone());
+    }
+
+    let paste = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Paste the error message to send along?")
+        .default(true)
+        .interact()?;
+    if !paste {
+        return Ok(String::new());
+    }
+
+    println!("Paste the error, then press Enter on an empty line:");
+    let mut pasted = String::new();
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            break;
+        }
+        pasted.push_str(&line);
+        pasted.push('\n');
+    }
+    Ok(tail(&pasted, fix::FIX_STDERR_BYTES).to_string())
+}
+
+/// A suggested command after the user reviewed it, and how it ran if it did
+struct Outcome {
+    command: String,
+    /// The exchange that led to the command, ending with a user message
+    conversation: Vec<Message>,
+    snapshot: Option<trash::Snapshot>,
+    execution: Option<Result<Execution>>,
+}
+
+impl Outcome {
+    /// Turns a command that failed to start or exited with an error into an
+    /// error. Only called once the history, and with it any snapshot of the
+    /// files the command touched, has been saved.
+    fn into_result(self) -> Result<()> {
+        match self.execution {
+            Some(result) => result?.check(&self.command),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Shows a suggested command and, unless --no-execute is given, runs it once
+/// the user approves it (or straight away with --yes). The conversation is
+/// what the command was suggested for, and is continued if it is refined.
+async fn review_and_run(
+    mut command: String,
+    mut conversation: Vec<Message>,
+    args: &RunArgs,
+    config: &Config,
+) -> Result<Outcome> {
+    // Commands that escalate privileges are never run without confirmation,
+    // unless the user has explicitly allowed it in the config
+    let escalation = safety::privilege_escalation(&command);
+    let auto_run = args.yes && !args.sandbox && (escalation.is_none() || config.allow_sudo());
+    let preview = args.preview || config.preview();
+    let explain = args.explain || config.explain();
+    let mut snapshot = None;
+    let mut execution = None;
 
     // Skip confirmation and run the command if --yes flag is set
-    if cli.yes {
+    if auto_run {
         println!("$ {}", command);
-        if !cli.no_execute {
-            execute_command(&command, &config.shell())?;
+        if preview {
+            show_preview(&command, &config.shell());
+        }
+        if !args.no_execute {
+            let (saved, result) = run_confirmed(&command, config);
+            snapshot = saved;
+            execution = Some(result);
         }
     } else {
         // Show the command and ask for confirmation
         println!("Suggested command:");
         println!("$ {}", command);
+        if explain {
+            describe_command(&command, config).await;
+        }
+
+        if let Some(tool) = escalation {
+            warn_privileged(tool);
+
+            if args.yes {
+                eprintln!(
+                    "Not running automatically. Set allow-sudo = true in your config to allow this."
+                );
+            }
+
+            if !args.no_execute {
+                let ask_alternative = Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt(format!("Ask for an alternative that doesn't use {}?", tool))
+                    .default(false)
+                    .interact()?;
 
-        if !cli.no_execute {
-            // Ask for confirmation
-            let confirmation = Confirm::with_theme(&ColorfulTheme::default())
-                .with_prompt("Run this command?")
-                .default(true)
-                .interact()?;
+                if ask_alternative {
+                    let prompt = conversation
+                        .last()
+                        .map(|message| message.content.clone())
+                        .unwrap_or_default();
+                    let alternative_prompt = safety::unprivileged_prompt(&prompt, &command);
+                    conversation.push(Message::assistant(command.as_str()));
+                    conversation.push(Message::user(alternative_prompt));
+                    command = converse(&conversation, config.system_prompt(), config).await?;
 
-            if confirmation {
-                execute_command(&command, &config.shell())?;
+                    println!("Suggested command:");
+                    println!("$ {}", command);
+                    if explain {
+                        describe_command(&command, config).await;
+                    }
+
+                    if let Some(tool) = safety::privilege_escalation(&command) {
+                        warn_privileged(tool);
+                    }
+                }
             }
         }
+
+        loop {
+            if preview {
+                show_preview(&command, &config.shell());
+            }
+
+            // The sandbox never touches the real files, so it also runs with
+            // --no-execute
+            if args.sandbox {
+                match sandbox::execute_sandboxed(&command, &config.shell()) {
+                    Ok(run) => sandbox::print_report(&run),
+                    Err(e) => eprintln!("Warning: the sandbox run failed: {:#}", e),
+                }
+            }
+
+            if args.no_execute {
+                break;
+            }
+
+            match ask_to_run(args.sandbox)? {
+                Choice::Run => {
+                    let (saved, result) = run_confirmed(&command, config);
+                    snapshot = saved;
+                    execution = Some(result);
+                    break;
+                }
+                Choice::Edit => match edit_command(&command)? {
+                    Some(edited) => {
+                        command = edited;
+                        println!("$ {}", command);
+                        if explain {
+                            describe_command(&command, config).await;
+                        }
+
+                        if let Some(tool) = safety::privilege_escalation(&command) {
+                            warn_privileged(tool);
+                        }
+                    }
+                    None => break,
+                },
+                Choice::Refine => {
+                    let Some(refinement) = ask_refinement()? else {
+                        continue;
+                    };
+
+                    // An edited command is sent as if the model had suggested
+                    // it, so the refinement applies to what the user sees
+                    conversation.push(Message::assistant(command.as_str()));
+                    conversation.push(Message::user(refinement));
+                    command = converse(&conversation, config.system_prompt(), config).await?;
+
+                    println!("Suggested command:");
+                    println!("$ {}", command);
+                    if explain {
+                        describe_command(&command, config).await;
+                    }
+
+                    if let Some(tool) = safety::privilege_escalation(&command) {
+                        warn_privileged(tool);
+                    }
+                }
+                Choice::Cancel => break,
+            }
+        }
+    }
+
+    Ok(Outcome {
+        command,
+        conversation,
+        snapshot,
+        execution,
+    })
+}
+
+/// Prints a short description of a suggested command. The command can still
+/// be reviewed without one, so failures are only reported.
+async fn describe_command(command: &str, config: &Config) {
+    let config = config.with_min_max_tokens(explain::DESCRIBE_MAX_TOKENS);
+
+    match ask_model(command, explain::describe_system_prompt(&config), &config).await {
+        Ok(description) => {
+            for line in description.trim().lines() {
+                println!("  {}", line);
+            }
+        }
+        Err(e) => eprintln!("Warning: could not explain the command: {:#}", e),
     }
+}
 
+fn record_in_history(
+    prompt: &str,
+    outcome: &Outcome,
+    model_name: &str,
+    config: &Config,
+) -> Result<()> {
     // Load history
-    let mut history = history::History::load()?;
+    let mut history = history::History::load_with(config)?;
 
     // Add the prompt and command to history
-    history.add_entry(&prompt, &command, false, &model_name);
+    let mut entry = history::HistoryEntry::new(prompt, &outcome.command, false, model_name);
+    entry.snapshot = outcome.snapshot.clone();
+    if let Some(Ok(execution)) = &outcome.execution {
+        entry.record_execution(execution, config.history_output_size());
+    }
+    history.push(entry);
 
     // Save history
-    history.save()?;
-
-    Ok(())
+    history.save()
 }
 
 fn main() -> ! {
@@ -196,14 +1717,14 @@ mod tests {
     #[tokio::test]
     async fn test_get_command_suggestion() {
         // Set up a mock config for testing
-        let mut config = Config::default();
+        let mut config = Config::default().without_tool_detection();
         let mut models = HashMap::new();
         models.insert(
             "mock".to_string(),
             ModelConfig {
                 provider: "mock".to_string(),
-                model: None,
                 auth_token: "test-token".to_string(),
+                ..ModelConfig::default()
             },
         );
         config.set_models(models);
@@ -224,32 +1745,110 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_get_prompt_from_stdin() {
-        // This is hard to test without mocking stdin, so we'll skip for now
+    #[tokio::test]
+    async fn test_converse() {
+        // Both the default model and the one the other test selects through
+        // HAI_DEFAULT_MODEL use the mock provider, as tests run in parallel
+        let mut config = Config::default().without_tool_detection();
+        let mut models = HashMap::new();
+        for name in [config.default_model(), "mock".to_string()] {
+            models.insert(
+                name,
+                ModelConfig {
+                    provider: "mock".to_string(),
+                    auth_token: "test-token".to_string(),
+                    ..ModelConfig::default()
+                },
+            );
+        }
+        config.set_models(models);
+
+        // The reply is to the latest message, not the first
+        let conversation = [
+            Message::user("list all files"),
+            Message::assistant("ls -la"),
+            Message::user("find all text files"),
+        ];
+        let reply = converse(&conversation, config.system_prompt(), &config)
+            .await
+            .unwrap();
+        assert_eq!(reply, "find . -name \"*.txt\"");
     }
 
     #[test]
-    fn test_execute_command() {
-        // Test with a simple command that should always succeed
-        let result = execute_command("echo test", "bash");
-        assert!(result.is_ok());
+    fn test_cli_history_subcommand() {
+        let cli = Cli::try_parse_from(["hai", "history", "purge", "--matching", "secret"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::History {
+                action: Some(HistoryAction::Purge { ref matching })
+            }) if matching == "secret"
+        ));
 
-        // Test with a command that should fail
-        let result = execute_command("exit 1", "bash");
-        assert!(result.is_err());
-    }
+        // Ordinary prompts are not mistaken for subcommands
+        let cli = Cli::try_parse_from(["hai", "show my shell history"]).unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(cli.prompt, "show my shell history");
 
-    #[test]
-    fn test_execute_command_with_different_shells() {
-        // This test is limited since we can't easily mock Command execution
-        // But we can at least test that the function doesn't panic with different shells
+        let cli = Cli::try_parse_from(["hai", "-n", "--preview", "delete logs"]).unwrap();
+        assert!(cli.run.no_execute && cli.run.preview);
 
-        // Test with bash (should be available on most test systems)
-        let result = execute_command("echo 'test'", "bash");
-        assert!(result.is_ok());
+        let cli = Cli::try_parse_from(["hai", "--sandbox", "-y", "tidy up"]).unwrap();
+        assert!(cli.run.sandbox && cli.run.yes);
+
+        let cli =
+            Cli::try_parse_from(["hai", "--plan", "--save-script", "setup.sh", "set up"]).unwrap();
+        assert!(cli.plan);
+        assert_eq!(cli.save_script, Some(PathBuf::from("setup.sh")));
+        assert!(Cli::try_parse_from(["hai", "--save-script", "setup.sh", "set up"]).is_err());
+        assert!(Cli::try_parse_from(["hai", "--plan", "--sandbox", "set up"]).is_err());
+
+        let cli = Cli::try_parse_from(["hai", "--shell-mode", "login", "update"]).unwrap();
+        assert_eq!(cli.run.shell_mode, Some(ShellMode::Login));
+        assert!(Cli::try_parse_from(["hai", "--shell-mode", "fancy", "update"]).is_err());
+
+        let cli = Cli::try_parse_from(["hai", "-i", "-m", "claude"]).unwrap();
+        assert!(cli.interactive);
+        assert_eq!(cli.run.model.as_deref(), Some("claude"));
+
+        let cli = Cli::try_parse_from(["hai", "tools", "--refresh"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Tools { refresh: true })
+        ));
+
+        let cli = Cli::try_parse_from(["hai", "--pipe", "names of crashing pods"]).unwrap();
+        assert!(cli.pipe && cli.command.is_none());
+        assert!(Cli::try_parse_from(["hai", "--pipe", "--plan", "x"]).is_err());
+
+        let cli = Cli::try_parse_from(["hai", "--context", "run the tests"]).unwrap();
+        assert!(cli.run.context);
+
+        let cli = Cli::try_parse_from(["hai", "--explain", "tidy up"]).unwrap();
+        assert!(cli.run.explain && !cli.run.yes);
+
+        let cli = Cli::try_parse_from(["hai", "undo", "-y"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Undo { yes: true })));
+
+        let cli = Cli::try_parse_from(["hai", "fix", "-e", "127", "-n", "gti status"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Fix {
+                command: Some(ref command),
+                exit_code: Some(127),
+                run: RunArgs { no_execute: true, .. },
+            }) if command == "gti status"
+        ));
 
-        // We can't reliably test fish or powershell as they might not be installed
-        // on the test system, but the code path should be covered by the implementation
+        let cli = Cli::try_parse_from(["hai", "explain", "tar -xzvf a.tgz"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Explain { command: Some(ref command) }) if command == "tar -xzvf a.tgz"
+        ));
+    }
+
+    #[test]
+    fn test_get_prompt_from_stdin() {
+        // This is hard to test without mocking stdin, so we'll skip for now
     }
 }
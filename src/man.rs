@@ -0,0 +1,201 @@
+use anyhow::{Context, Result};
+use clap::Command;
+use clap_mangen::roff::{bold, italic, roman, Roff};
+use clap_mangen::Man;
+use std::io::Write;
+use std::path::Path;
+
+/// Sections of hai(1) that aren't part of the command line definition, as
+/// lists of terms and their descriptions
+const FILES: [(&str, &str); 7] = [
+    (
+        "~/.config/hai/config.toml",
+        "User configuration file, see hai-config(5).",
+    ),
+    (
+        "/etc/hai/config.toml",
+        "System-wide configuration file, overridden by the user's.",
+    ),
+    (
+        ".hai.toml",
+        "Project configuration file in the current directory or a parent \
+         directory, which overrides the user's.",
+    ),
+    (
+        "~/.config/hai/history.json",
+        "Command history file. Secrets are redacted before it is written (see \
+         redact-history in hai-config(5)) and the file is only readable by the \
+         current user.",
+    ),
+    (
+        "~/.config/hai/state/",
+        "Directory and environment changes waiting to be applied by the shell \
+         integration.",
+    ),
+    (
+        "~/.config/hai/tools.json",
+        "The tools found on PATH, scanned again when PATH changes or by hai \
+         tools --refresh.",
+    ),
+    (
+        "~/.config/hai/trash/",
+        "Files saved before running destructive commands, used by hai undo.",
+    ),
+];
+
+const ENVIRONMENT: [(&str, &str); 6] = [
+    (
+        "HAI_CONFIG",
+        "Use this config file instead of ~/.config/hai/config.toml.",
+    ),
+    ("HAI_DEFAULT_MODEL", "Override the default model to use."),
+    ("HAI_OPENAI_TOKEN", "Set the OpenAI API token."),
+    ("HAI_ANTHROPIC_TOKEN", "Set the Anthropic API token."),
+    (
+        "HAI_LAST_COMMAND, HAI_LAST_STATUS",
+        "The previous command and its exit status, for hai fix. Meant to be \
+         set by the shell integration from hai init.",
+    ),
+    ("HISTFILE", "The bash or zsh history file read by hai fix."),
+];
+
+const EXAMPLES: [(&str, &str); 7] = [
+    (
+        "Convert a natural language request into a shell command:",
+        "hai \"find all png files in the current directory\"",
+    ),
+    (
+        "Use a specific model:",
+        "hai --model claude-3 \"show top processes by memory usage\"",
+    ),
+    (
+        "Get a command that fits the format of some data:",
+        "head -n 20 access.log | hai \"count requests per IP\"",
+    ),
+    (
+        "Transform data in the middle of a pipeline:",
+        "kubectl get pods -o json | hai --pipe \"names of crashing pods\"",
+    ),
+    (
+        "Run the suggested command without asking:",
+        "hai -y \"count words in README.md\"",
+    ),
+    (
+        "Add an API key to the config file:",
+        "hai config set models.claude-3.auth-token sk-ant-...",
+    ),
+    (
+        "Fix the last command with the shell integration loaded:",
+        "hai fix",
+    ),
+];
+
+const EXIT_STATUS: [(&str, &str); 4] = [
+    ("0", "Success"),
+    ("1", "General error"),
+    ("2", "Configuration error"),
+    ("3", "Network or API error"),
+];
+
+/// Writes hai(1) and a page for each subcommand, such as hai-fix(1)
+pub fn write_pages(cmd: Command, dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    clap_mangen::generate_to(cmd.clone(), dir)
+        .with_context(|| format!("Failed to write man pages to {}", dir.display()))?;
+
+    // Replace the plain hai(1) with the one that has the extra sections
+    let mut page = Vec::new();
+    render(cmd, &mut page)?;
+    let path = dir.join("hai.1");
+    std::fs::write(&path, page).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Renders hai(1): the sections clap_mangen generates from the command line
+/// definition, followed by the ones it doesn't know about
+pub fn render(cmd: Command, out: &mut dyn Write) -> Result<()> {
+    let man = Man::new(cmd.disable_help_subcommand(true));
+    man.render_title(out)?;
+    man.render_name_section(out)?;
+    man.render_synopsis_section(out)?;
+    man.render_description_section(out)?;
+    man.render_options_section(out)?;
+    man.render_subcommands_section(out)?;
+
+    let mut roff = Roff::new();
+    roff.control("SH", ["CONFIGURATION"]);
+    roff.text([
+        roman("hai reads its settings from "),
+        italic("/etc/hai/config.toml"),
+        roman(", then "),
+        italic("~/.config/hai/config.toml"),
+        roman(" and then the nearest "),
+        italic(".hai.toml"),
+        roman(", each overriding the ones before it. See "),
+        bold("hai-config"),
+        roman("(5) for the file format."),
+    ]);
+    definitions(&mut roff, "FILES", &FILES);
+    definitions(&mut roff, "ENVIRONMENT", &ENVIRONMENT);
+
+    roff.control("SH", ["EXAMPLES"]);
+    for (description, command) in EXAMPLES {
+        roff.control("PP", []).text([roman(description)]);
+        roff.control("IP", ["", "4"])
+            .text([bold(format!("$ {}", command))]);
+    }
+
+    definitions(&mut roff, "EXIT STATUS", &EXIT_STATUS);
+    roff.control("SH", ["BUGS"]).text([roman(
+        "Report bugs to: https://github.com/gregbell/hai/issues",
+    )]);
+    roff.control("SH", ["SEE ALSO"])
+        .text([bold("hai-config"), roman("(5)")]);
+    roff.to_writer(out)?;
+
+    man.render_version_section(out)?;
+    man.render_authors_section(out)?;
+    Ok(())
+}
+
+fn definitions(roff: &mut Roff, title: &str, terms: &[(&str, &str)]) {
+    roff.control("SH", [title]);
+    for (term, description) in terms {
+        roff.control("TP", [])
+            .text([bold(*term)])
+            .text([roman(*description)]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+    use tempfile::tempdir;
+
+    fn command() -> Command {
+        Command::new("hai")
+            .version("1.0")
+            .about("Turns prompts into commands")
+            .arg(Arg::new("yes").short('y').long("yes").help("Run it"))
+            .subcommand(Command::new("fix").about("Fix the last command"))
+            .subcommand(Command::new("__save-state").hide(true))
+    }
+
+    #[test]
+    fn test_write_pages() {
+        let dir = tempdir().unwrap();
+        write_pages(command(), dir.path()).unwrap();
+
+        let page = std::fs::read_to_string(dir.path().join("hai.1")).unwrap();
+        assert!(page.starts_with(".ie \\n(.g .ds Aq \\(aq"));
+        assert!(page.contains(".TH hai 1"));
+        assert!(page.contains("\\-\\-yes"));
+        assert!(page.contains("hai\\-fix(1)"));
+        assert!(page.contains(".SH ENVIRONMENT"));
+        assert!(page.contains("HAI_DEFAULT_MODEL"));
+
+        assert!(dir.path().join("hai-fix.1").exists());
+        assert!(!dir.path().join("hai-__save-state.1").exists());
+        assert!(!dir.path().join("hai-help.1").exists());
+    }
+}
@@ -0,0 +1,375 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::preview;
+use crate::safety;
+
+/// System prompt for `--pipe`
+pub const PIPE_SYSTEM_PROMPT: &str = "
+You are Hai, a helpful AI that converts natural language to shell commands.
+The user's data will be piped to your command on standard input, and its standard output is passed on to the next command in a pipeline.
+Respond with ONLY a single command that reads the data from standard input and writes the result to standard output, no explanations or markdown formatting.
+Write only the result, without headers or messages, in a format the next command can use.
+Never read, write or delete files, and never ask for input.
+Only use standard text filters such as jq, awk, sed, grep, sort, uniq, cut, tr, head and wc.
+Make sure the command is compatible with the user's environment and shell.
+";
+
+/// Programs that only read standard input or their arguments and write to
+/// standard output. `--pipe` refuses every other program, since it can't know
+/// what an unfamiliar one does; the few options that make these write files
+/// are checked in [`writes_files`].
+#[rustfmt::skip]
+const READ_ONLY_FILTERS: &[&str] = &[
+    "jq", "awk", "gawk", "mawk", "sed", "grep", "egrep", "fgrep", "rg", "sort", "uniq",
+    "cut", "tr", "head", "tail", "wc", "cat", "tac", "nl", "paste", "column", "fold",
+    "fmt", "rev", "expand", "unexpand", "base64", "md5sum", "sha1sum", "sha256sum",
+    "printf", "echo", "seq", "numfmt", "comm", "join", "true",
+];
+
+/// The system prompt for a filter command, with the user's environment
+pub fn system_prompt(config: &Config) -> String {
+    config.with_environment(PIPE_SYSTEM_PROMPT)
+}
+
+/// Nobody confirms a filter command before it runs, so only known read-only
+/// filters are allowed, and never with elevated privileges or redirections
+/// that change files
+pub fn check(command: &str, cwd: &Path) -> Result<()> {
+    if let Some(tool) = safety::privilege_escalation(command) {
+        return Err(anyhow::anyhow!(
+            "Refusing to run `{}` with --pipe: it uses {}",
+            command,
+            tool
+        ));
+    }
+
+    for segment in safety::split_simple_commands(command) {
+        let words = safety::executed_words(&segment);
+        let Some(program) = safety::executed_program(&segment) else {
+            continue;
+        };
+        if !READ_ONLY_FILTERS.contains(&program.as_str()) {
+            return Err(anyhow::anyhow!(
+                "Refusing to run `{}` with --pipe: {} is not a known read-only filter",
+                command,
+                program
+            ));
+        }
+        if writes_files(&program, &words[1..]) {
+            return Err(anyhow::anyhow!(
+                "Refusing to run `{}` with --pipe: `{}` may change files",
+                command,
+                segment
+            ));
+        }
+    }
+
+    if let Some(target) = preview::affected_files(command, cwd).first() {
+        return Err(anyhow::anyhow!(
+            "Refusing to run `{}` with --pipe: it would {} {}",
+            command,
+            target.action,
+            target.path.display()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Whether a filter is told to write files or run commands: `sed -i` and
+/// sed's `w` and `e` commands, awk's `print > file`, pipes and `system()`,
+/// programs read from files, `sort -o` and `--compress-program`, `rg --pre`
+/// and uniq's output file operand
+fn writes_files(program: &str, args: &[String]) -> bool {
+    match program {
+        "sed" => sed_scripts(args).is_none_or(|scripts| scripts.iter().any(|s| sed_writes(s))),
+        "awk" | "gawk" | "mawk" => awk_program(args).is_none_or(awk_writes),
+        "sort" => args.iter().any(|arg| {
+            arg.starts_with("--output")
+                || arg.starts_with("--compress-program")
+                || (is_short_flags(arg) && arg.contains('o'))
+        }),
+        "rg" => args
+            .iter()
+            .any(|arg| arg == "--pre" || arg.starts_with("--pre=")),
+        "uniq" => operands(args, &["-f", "-s", "-w"]).len() > 1,
+        _ => false,
+    }
+}
+
+/// A group of one-letter options such as `-rn`
+fn is_short_flags(arg: &str) -> bool {
+    arg.len() > 1 && arg.starts_with('-') && !arg.starts_with("--")
+}
+
+/// The arguments that aren't options, skipping the values of the options in
+/// `value_options`
+fn operands<'a>(args: &'a [String], value_options: &[&str]) -> Vec<&'a String> {
+    let mut operands = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            operands.extend(args.by_ref());
+        } else if value_options.contains(&arg.as_str()) {
+            args.next();
+        } else if !arg.starts_with('-') || arg == "-" {
+            operands.push(arg);
+        }
+    }
+    operands
+}
+
+/// Every script given to sed, with `-e`, `--expression` or as the first
+/// operand. `None` if sed would edit files in place or read its script from
+/// a file, which can't be checked.
+fn sed_scripts(args: &[String]) -> Option<Vec<&str>> {
+    let mut scripts = Vec::new();
+    let mut operand = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            operand = operand.or(args.next());
+            break;
+        }
+        if let Some(long) = arg.strip_prefix("--") {
+            match long.split_once('=') {
+                Some(("expression", script)) => scripts.push(script),
+                _ if long == "expression" => scripts.push(args.next()?.as_str()),
+                _ if long.starts_with("in-place") || long.starts_with("file") => return None,
+                // --line-length takes a value
+                _ if long == "line-length" => {
+                    args.next();
+                }
+                _ => {}
+            }
+        } else if is_short_flags(arg) {
+            // In a group such as -ne, the value is the rest of the group or
+            // the next word
+            for (i, flag) in arg.char_indices().skip(1) {
+                match flag {
+                    'i' | 'f' => return None,
+                    'e' | 'l' => {
+                        let rest = &arg[i + 1..];
+                        let value = if rest.is_empty() {
+                            args.next()?.as_str()
+                        } else {
+                            rest
+                        };
+                        if flag == 'e' {
+                            scripts.push(value);
+                        }
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        } else if operand.is_none() {
+            operand = Some(arg);
+        }
+    }
+
+    if scripts.is_empty() {
+        scripts.push(operand?.as_str());
+    }
+    Some(scripts)
+}
+
+/// Whether a sed script has a `w` (write file) or `e` (execute) command,
+/// after any address, or an `s` command with the `w` or `e` flag
+fn sed_writes(script: &str) -> bool {
+    script.split([';', '\n']).any(|command| {
+        let command = skip_sed_address(command);
+        if command.starts_with(['w', 'W', 'e']) {
+            return true;
+        }
+        // The flags of s/a/b/flags, e.g. s/x/y/gw out.txt
+        let mut chars = command.chars();
+        match (chars.next(), chars.next()) {
+            (Some('s'), Some(delimiter)) => command
+                .rsplit(delimiter)
+                .next()
+                .is_some_and(|flags| flags.contains(['w', 'e'])),
+            _ => false,
+        }
+    })
+}
+
+/// The command after the address of a sed command, such as `w out` in
+/// `/x/,$!w out`. Addresses are line numbers, `$`, `first~step`, `/regex/`
+/// and `\%regex%`, joined by `,` and followed by `!`, and blocks open with
+/// `{`.
+fn skip_sed_address(command: &str) -> &str {
+    let mut rest = command;
+    loop {
+        rest = rest.trim_start();
+        let mut chars = rest.chars();
+        let (skipped, regex) = match chars.next() {
+            Some(c) if c.is_ascii_digit() || matches!(c, '$' | ',' | '!' | '~' | '{') => (1, false),
+            Some('/') => (
+                regex_end(&rest[1..], '/').map_or(rest.len(), |end| end + 2),
+                true,
+            ),
+            Some('\\') => match chars.next() {
+                Some(delimiter) => {
+                    let start = 1 + delimiter.len_utf8();
+                    let end = regex_end(&rest[start..], delimiter);
+                    (end.map_or(rest.len(), |end| start + end + 1), true)
+                }
+                None => (rest.len(), false),
+            },
+            _ => break,
+        };
+        rest = &rest[skipped..];
+        // The I and M flags of a regex address
+        if regex {
+            rest = rest.trim_start_matches(['I', 'M']);
+        }
+    }
+    rest
+}
+
+/// Where the unescaped `delimiter` that ends a regex is in `text`
+fn regex_end(text: &str, delimiter: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == delimiter => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The program given to awk, skipping `-v var=value` and `-F sep`. `None` if
+/// the program or extensions are read from files, which can't be checked.
+fn awk_program(args: &[String]) -> Option<&str> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--" => return args.next().map(String::as_str),
+            "-v" | "--assign" | "-F" | "--field-separator" => {
+                args.next();
+            }
+            arg if arg.starts_with("--") => {
+                let name = arg[2..].split('=').next().unwrap_or_default();
+                if matches!(name, "file" | "include" | "load" | "exec" | "source") {
+                    return None;
+                }
+            }
+            arg if is_short_flags(arg) => {
+                // -vx=1 and -F: carry their value, -f, -E, -i and -l read
+                // files
+                if arg[1..].starts_with(['f', 'E', 'i', 'l', 'e']) {
+                    return None;
+                }
+            }
+            program => return Some(program),
+        }
+    }
+    None
+}
+
+/// Whether an awk program redirects `print` or `printf` to a file or a
+/// command, reads from a command or calls `system()`
+fn awk_writes(program: &str) -> bool {
+    if program.contains("system(") || program.contains("|&") {
+        return true;
+    }
+    let piped_getline = program
+        .split('|')
+        .skip(1)
+        .any(|after| after.trim_start().starts_with("getline"));
+    let redirected_print = program.match_indices("print").any(|(i, _)| {
+        program[i..]
+            .split([';', '}', '\n'])
+            .next()
+            .is_some_and(|statement| statement.contains(['>', '|']))
+    });
+    piped_getline || redirected_print
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_check() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("pods.json"), "{}").unwrap();
+
+        assert!(check("jq -r '.items[].metadata.name'", dir.path()).is_ok());
+        assert!(check("sort | uniq -c | sort -rn", dir.path()).is_ok());
+
+        let error = check("jq . > pods.json", dir.path()).unwrap_err();
+        assert!(error.to_string().contains("it would overwrite"));
+        assert!(check("awk '$3 > 100 { print $1 }'", dir.path()).is_ok());
+        assert!(check("sed -n 's/^name: //p' | sort -u", dir.path()).is_ok());
+
+        assert!(check("xargs -0 rm", dir.path()).is_err());
+        assert!(check("xargs -n 1 rm", dir.path()).is_err());
+        assert!(check("xargs -I {} rm {}", dir.path()).is_err());
+        assert!(check("perl -i -pe 's/a/b/' pods.json", dir.path()).is_err());
+        assert!(check("python3 -c 'import os'", dir.path()).is_err());
+        assert!(check("curl -o out.html https://example.com", dir.path()).is_err());
+        assert!(check("find . -exec rm {} +", dir.path()).is_err());
+        assert!(check("kubectl delete pods --all", dir.path()).is_err());
+        assert!(check("awk '{ print > \"out.txt\" }'", dir.path()).is_err());
+        assert!(check("awk '{ system(\"rm \" $1) }'", dir.path()).is_err());
+        assert!(check("sed -i 's/a/b/' pods.json", dir.path()).is_err());
+        assert!(check("sed 's/a/b/w out.txt'", dir.path()).is_err());
+        assert!(check("sed -n '1w out.txt'", dir.path()).is_err());
+        assert!(check("sort -o pods.json", dir.path()).is_err());
+        assert!(check("uniq - out.txt", dir.path()).is_err());
+        assert!(check("uniq -f 1 -", dir.path()).is_ok());
+        assert!(check("jq -r .name | tee names.txt", dir.path()).is_err());
+        assert!(check("sudo tee /etc/hosts", dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_check_sed() {
+        let dir = tempdir().unwrap();
+        assert!(check("sed -n -e '/^#/d' -e p", dir.path()).is_ok());
+        assert!(check("sed -ne 's/a/b/gp'", dir.path()).is_ok());
+        assert!(check("sed -E '/error/,$!d'", dir.path()).is_ok());
+
+        assert!(check("sed -e p -e 'w out'", dir.path()).is_err());
+        assert!(check("sed --expression='1e date'", dir.path()).is_err());
+        assert!(check("sed -n '/x/w out'", dir.path()).is_err());
+        assert!(check("sed -n '\\%x%,$W out'", dir.path()).is_err());
+        assert!(check("sed -n '/a\\/b/I w out'", dir.path()).is_err());
+        assert!(check("sed -f script.sed", dir.path()).is_err());
+        assert!(check("sed -ni p data.txt", dir.path()).is_err());
+        assert!(check("sed --in-place=.bak p data.txt", dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_check_awk() {
+        let dir = tempdir().unwrap();
+        assert!(check("awk -v min=10 '$2 > min { print $1 }'", dir.path()).is_ok());
+        assert!(check("awk -F: '{ print $1 }'", dir.path()).is_ok());
+
+        assert!(check("awk -v a=1 '{ print > \"out\" }'", dir.path()).is_err());
+        assert!(check("awk -F , '{ system($1) }'", dir.path()).is_err());
+        assert!(check("awk -f prog.awk", dir.path()).is_err());
+        assert!(check("gawk --file=prog.awk", dir.path()).is_err());
+        assert!(check("gawk -l filefuncs '{ print }'", dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_check_sort_and_rg() {
+        let dir = tempdir().unwrap();
+        assert!(check("sort -rn -k 2", dir.path()).is_ok());
+        assert!(check("rg -i error", dir.path()).is_ok());
+
+        assert!(check("sort -ro out", dir.path()).is_err());
+        assert!(check("sort --output=out", dir.path()).is_err());
+        assert!(check("sort --compress-program=sh", dir.path()).is_err());
+        assert!(check("rg --pre ./run.sh error", dir.path()).is_err());
+        assert!(check("rg --pre=./run.sh error", dir.path()).is_err());
+    }
+}
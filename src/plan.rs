@@ -0,0 +1,363 @@
+use anyhow::{Context, Result};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use crate::config::Config;
+
+/// A plan has room for many more tokens than a single command
+pub const PLAN_MAX_TOKENS: usize = 1024;
+
+/// System prompt for `--plan`
+pub const PLAN_SYSTEM_PROMPT: &str = "
+You are Hai, a helpful AI that converts natural language to shell commands.
+The user's request needs several commands. Respond with the commands to run, in order, one per line.
+Respond with ONLY the commands, no numbering, explanations or markdown formatting.
+Each command runs in a separate shell process that starts in the same directory, so don't rely on cd, source or exported variables from an earlier command: use paths such as venv/bin/pip instead.
+Make sure commands are compatible with the user's environment and shell.
+";
+
+/// The system prompt for planning, with the user's environment and, if
+/// enabled, their current directory
+pub fn system_prompt(config: &Config) -> String {
+    config.with_directory_context(&config.with_environment(PLAN_SYSTEM_PROMPT))
+}
+
+/// Turns the model's answer into a list of commands, dropping the markdown,
+/// numbering and comments it sometimes adds anyway. A command that goes on
+/// over several lines, with a trailing `\`, a heredoc or a block such as
+/// `if … fi` (`if … end` in fish), stays one step, with its lines as they
+/// were.
+pub fn parse_plan(response: &str, shell: &str) -> Vec<String> {
+    let mut steps: Vec<String> = Vec::new();
+    let mut open = false;
+
+    for line in response.lines() {
+        if open {
+            // The closing fence of a block the model never finished
+            if line.trim().starts_with("```") {
+                open = false;
+                continue;
+            }
+            let step = steps.last_mut().unwrap();
+            step.push('\n');
+            step.push_str(line.trim_end());
+            open = is_incomplete(step, shell);
+            continue;
+        }
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("```") || line.starts_with('#') {
+            continue;
+        }
+        let command = strip_marker(line);
+        if !command.is_empty() {
+            steps.push(command.to_string());
+            open = is_incomplete(command, shell);
+        }
+    }
+
+    steps
+}
+
+/// Removes a leading `1.`, `2)`, `-` or `$` from a line, and the backticks
+/// around a line that is wrapped in a single pair of them
+fn strip_marker(line: &str) -> &str {
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits > 0 {
+        if let Some(rest) = line[digits..]
+            .strip_prefix(". ")
+            .or_else(|| line[digits..].strip_prefix(") "))
+        {
+            return strip_marker(rest.trim_start());
+        }
+    }
+
+    for marker in ["- ", "* ", "$ "] {
+        if let Some(rest) = line.strip_prefix(marker) {
+            return strip_marker(rest.trim_start());
+        }
+    }
+
+    // `echo `date`` is a command substitution, not markdown
+    line.strip_prefix('`')
+        .and_then(|rest| rest.strip_suffix('`'))
+        .filter(|inner| !inner.contains('`'))
+        .unwrap_or(line)
+}
+
+/// Whether a command needs the lines after it: it ends with a `\`, a
+/// heredoc hasn't reached its delimiter, or a block isn't closed yet. Blocks
+/// are `if`, `case` and loops in POSIX shells, and `if`, `for`, `while`,
+/// `function`, `begin` and `switch` up to `end` in fish. Only keywords where
+/// a command starts count, and never in quotes or comments.
+fn is_incomplete(command: &str, shell: &str) -> bool {
+    let (openers, closers): (&[&str], &[&str]) = if shell == "fish" {
+        (
+            &["if", "for", "while", "function", "begin", "switch"],
+            &["end"],
+        )
+    } else {
+        (&["if", "case", "do"], &["fi", "esac", "done"])
+    };
+
+    let mut heredoc: Option<String> = None;
+    let mut depth = 0usize;
+    let mut continued = false;
+
+    for line in command.lines() {
+        if let Some(delimiter) = &heredoc {
+            if line.trim() == delimiter {
+                heredoc = None;
+            }
+            continue;
+        }
+
+        let words = unquoted_words(line);
+        continued = line.trim_end().ends_with('\\');
+        let mut previous: Option<&str> = None;
+        for (i, (word, quoted)) in words.iter().enumerate() {
+            let at_command = previous.is_none_or(|previous| COMMAND_STARTS.contains(&previous));
+            previous = Some(if *quoted { "" } else { word.as_str() });
+
+            if let Some(delimiter) = word.strip_prefix("<<").filter(|d| !d.starts_with('<')) {
+                let delimiter = delimiter.trim_start_matches('-');
+                heredoc = if delimiter.is_empty() {
+                    words.get(i + 1).map(|(next, _)| next.clone())
+                } else {
+                    Some(delimiter.to_string())
+                };
+                continue;
+            }
+            if *quoted || !at_command {
+                continue;
+            }
+            // fish's `else if` continues the same block
+            let else_if = shell == "fish" && word == "if" && i > 0 && words[i - 1].0 == "else";
+            if openers.contains(&word.as_str()) && !else_if {
+                depth += 1;
+            } else if closers.contains(&word.as_str()) {
+                depth = depth.saturating_sub(1);
+            }
+        }
+    }
+
+    heredoc.is_some() || depth > 0 || continued
+}
+
+/// Words after which a new command starts, so that a keyword there opens or
+/// closes a block while `echo for` doesn't
+const COMMAND_STARTS: &[&str] = &[
+    ";", "&&", "||", "|", "&", "then", "do", "else", "elif", "and", "or", "not", "begin", "{", "!",
+];
+
+/// The words of a line of shell, split at whitespace and with `;` as a word
+/// of its own, up to any comment, with their quotes removed and whether they had any: a quoted
+/// word such as `"if"` is never a keyword
+fn unquoted_words(line: &str) -> Vec<(String, bool)> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    let mut quote = None;
+
+    for c in line.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.push(c),
+            None => match c {
+                '\'' | '"' => {
+                    quote = Some(c);
+                    quoted = true;
+                }
+                '#' if word.is_empty() && !quoted => break,
+                c if c.is_whitespace() || c == ';' => {
+                    if !word.is_empty() || quoted {
+                        words.push((std::mem::take(&mut word), quoted));
+                    }
+                    if c == ';' {
+                        words.push((";".to_string(), false));
+                    }
+                    quoted = false;
+                }
+                c => word.push(c),
+            },
+        }
+    }
+    if !word.is_empty() || quoted {
+        words.push((word, quoted));
+    }
+    words
+}
+
+/// Builds a script that runs the steps in order and stops at the first one
+/// that fails
+pub fn script(steps: &[String], prompt: &str, shell: &str) -> String {
+    let (shebang, stop_on_error) = match shell {
+        "fish" => ("#!/usr/bin/env fish", None),
+        "powershell" | "pwsh" => (
+            "#!/usr/bin/env pwsh",
+            Some("$ErrorActionPreference = 'Stop'"),
+        ),
+        "zsh" => ("#!/usr/bin/env zsh", Some("set -e")),
+        _ => ("#!/usr/bin/env bash", Some("set -e")),
+    };
+
+    let mut script = format!("{}\n", shebang);
+    for line in prompt.trim().lines() {
+        script.push_str(&format!("# {}\n", line));
+    }
+    if let Some(stop_on_error) = stop_on_error {
+        script.push_str(&format!("\n{}\n", stop_on_error));
+    }
+    script.push('\n');
+
+    for step in steps {
+        // fish has no `set -e`
+        if shell == "fish" {
+            script.push_str(&format!("{}; or exit $status\n", step));
+        } else {
+            script.push_str(&format!("{}\n", step));
+        }
+    }
+
+    script
+}
+
+/// Writes the plan as an executable script. An existing file is never
+/// overwritten.
+pub fn save_script(path: &Path, steps: &[String], prompt: &str, shell: &str) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o755);
+    }
+
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    file.write_all(script(steps, prompt, shell).as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_plan() {
+        let response = "```bash\n\
+                        1. python3 -m venv venv\n\
+                        2) venv/bin/pip install -r requirements.txt\n\
+                        # run the tests\n\
+                        $ venv/bin/pytest\n\
+                        ```\n";
+        assert_eq!(
+            parse_plan(response, "bash"),
+            vec![
+                "python3 -m venv venv",
+                "venv/bin/pip install -r requirements.txt",
+                "venv/bin/pytest",
+            ]
+        );
+
+        // Commands that merely start with a number are left alone
+        assert_eq!(
+            parse_plan("7z x archive.7z\n", "bash"),
+            vec!["7z x archive.7z"]
+        );
+
+        // Backticks are only removed when they wrap the whole line
+        assert_eq!(
+            parse_plan("`mkdir -p logs`\necho `date` > logs/start\n", "bash"),
+            vec!["mkdir -p logs", "echo `date` > logs/start"]
+        );
+    }
+
+    #[test]
+    fn test_parse_plan_multiline() {
+        let response = r#"```bash
+cat > .env <<'EOF'
+# settings
+DEBUG=1
+EOF
+if [ ! -d venv ]; then
+  python3 -m venv venv
+fi
+docker run --rm \
+  -v "$PWD:/src" alpine ls /src
+git commit -m "fix if statement"
+for f in *.log; do gzip "$f"; done
+```"#;
+        assert_eq!(
+            parse_plan(response, "bash"),
+            vec![
+                "cat > .env <<'EOF'\n# settings\nDEBUG=1\nEOF",
+                "if [ ! -d venv ]; then\n  python3 -m venv venv\nfi",
+                "docker run --rm \\\n  -v \"$PWD:/src\" alpine ls /src",
+                "git commit -m \"fix if statement\"",
+                "for f in *.log; do gzip \"$f\"; done",
+            ]
+        );
+
+        // A block the model never closed ends at the closing fence
+        assert_eq!(
+            parse_plan("```\nif true; then\n  echo hi\n```\nls\n", "bash"),
+            vec!["if true; then\n  echo hi", "ls"]
+        );
+    }
+
+    #[test]
+    fn test_parse_plan_fish() {
+        let response = "for f in *.log\n    gzip $f\nend\n\
+                        if test -d venv\n    echo venv\nelse if test -d .venv\n    echo .venv\nend\n\
+                        echo for the end\n\
+                        function greet; echo hi; end\n";
+        assert_eq!(
+            parse_plan(response, "fish"),
+            vec![
+                "for f in *.log\n    gzip $f\nend",
+                "if test -d venv\n    echo venv\nelse if test -d .venv\n    echo .venv\nend",
+                "echo for the end",
+                "function greet; echo hi; end",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_script() {
+        let steps = vec!["mkdir -p build".to_string(), "make".to_string()];
+
+        let bash = script(&steps, "build it", "bash");
+        assert!(bash.starts_with("#!/usr/bin/env bash\n# build it\n"));
+        assert!(bash.contains("\nset -e\n"));
+        assert!(bash.ends_with("mkdir -p build\nmake\n"));
+
+        let fish = script(&steps, "build it", "fish");
+        assert!(fish.contains("make; or exit $status\n"));
+    }
+
+    #[test]
+    fn test_save_script() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("setup.sh");
+        let steps = vec!["echo hi".to_string()];
+
+        save_script(&path, &steps, "say hi", "bash").unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().contains("echo hi"));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_ne!(mode & 0o100, 0);
+        }
+
+        // Existing files are left alone
+        assert!(save_script(&path, &steps, "say hi", "bash").is_err());
+    }
+}
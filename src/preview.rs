@@ -0,0 +1,652 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::safety;
+
+/// Maximum number of files listed before the preview is truncated
+const MAX_LISTED_FILES: usize = 20;
+
+/// What a command is going to do to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Delete,
+    Move,
+    Overwrite,
+    Modify,
+    Create,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Action::Delete => "delete",
+            Action::Move => "move",
+            Action::Overwrite => "overwrite",
+            Action::Modify => "modify",
+            Action::Create => "create",
+        };
+        // Pad so that the file names line up
+        f.pad(name)
+    }
+}
+
+/// A file that would be touched by a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub action: Action,
+    pub path: PathBuf,
+    /// Where a moved file ends up
+    pub destination: Option<PathBuf>,
+}
+
+/// Works out which files a command would delete, move or overwrite, without
+/// running it. Globs are expanded relative to `cwd` and `find` expressions are
+/// run with their destructive actions replaced by `-print`.
+///
+/// Only `rm`, `mv`, `find -delete`/`-exec rm`, `sed -i` and output
+/// redirections are understood; anything else yields no targets.
+pub fn affected_files(command: &str, cwd: &Path) -> Vec<Target> {
+    let mut targets = Vec::new();
+
+    for segment in safety::split_simple_commands(command) {
+        let mut words = Vec::new();
+
+        for token in tokenize(&segment) {
+            match token {
+                Token::Word { text, pattern } => words.push(Word { text, pattern }),
+                Token::Redirect {
+                    append,
+                    target: Some(target),
+                } => {
+                    let path = expand_tilde(&target);
+                    let action = if append {
+                        Action::Modify
+                    } else if cwd.join(&path).exists() {
+                        Action::Overwrite
+                    } else {
+                        Action::Create
+                    };
+                    // Redirecting to /dev/null and friends is harmless
+                    if !path.starts_with("/dev") {
+                        targets.push(Target {
+                            action,
+                            path,
+                            destination: None,
+                        });
+                    }
+                }
+                Token::Redirect { target: None, .. } => {}
+            }
+        }
+
+        let texts: Vec<String> = words.iter().map(|w| w.text.clone()).collect();
+        let words = &words[safety::privileged_command_start(&texts)..];
+
+        let Some((program, args)) = words.split_first() else {
+            continue;
+        };
+
+        match program.text.rsplit('/').next().unwrap_or_default() {
+            "rm" | "rmdir" | "unlink" => targets.extend(
+                expand_all(positional(args), cwd)
+                    .into_iter()
+                    .filter(|path| cwd.join(path).exists())
+                    .map(|path| Target {
+                        action: Action::Delete,
+                        path,
+                        destination: None,
+                    }),
+            ),
+            "mv" => targets.extend(mv_targets(args, cwd)),
+            "sed" => targets.extend(sed_targets(args, cwd)),
+            "find" => targets.extend(find_targets(args, cwd)),
+            _ => {}
+        }
+    }
+
+    targets
+}
+
+/// Prints the preview produced by [`affected_files`]
+pub fn print_preview(targets: &[Target]) {
+    if targets.is_empty() {
+        return;
+    }
+
+    println!("Files affected:");
+    for target in targets.iter().take(MAX_LISTED_FILES) {
+        println!("  {:<9} {}", target.action, target.path.display());
+    }
+    if targets.len() > MAX_LISTED_FILES {
+        println!("  ... and {} more", targets.len() - MAX_LISTED_FILES);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Word {
+    text: String,
+    /// Whether the word contains unquoted glob characters
+    pattern: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word {
+        text: String,
+        pattern: bool,
+    },
+    Redirect {
+        append: bool,
+        target: Option<String>,
+    },
+}
+
+/// Splits a simple command into words and output redirections, handling
+/// quoting and backslash escapes the way POSIX shells do
+fn tokenize(segment: &str) -> Vec<Token> {
+    let mut tokenizer = Tokenizer::default();
+    let mut chars = segment.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => tokenizer.finish_word(),
+            '\'' => {
+                tokenizer.in_word = true;
+                for c in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                    tokenizer.current.push(c);
+                }
+            }
+            '"' => {
+                tokenizer.in_word = true;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' if matches!(chars.peek(), Some('"' | '\\' | '$')) => {
+                            tokenizer.current.push(chars.next().unwrap());
+                        }
+                        _ => tokenizer.current.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                tokenizer.in_word = true;
+                if let Some(c) = chars.next() {
+                    tokenizer.current.push(c);
+                }
+            }
+            '>' => {
+                // A leading file descriptor number (2>file) is not a word
+                if tokenizer.in_word && tokenizer.current.chars().all(|c| c.is_ascii_digit()) {
+                    tokenizer.current.clear();
+                    tokenizer.in_word = false;
+                }
+                tokenizer.finish_word();
+                let append = chars.peek() == Some(&'>');
+                if append || chars.peek() == Some(&'|') {
+                    chars.next();
+                }
+                tokenizer.redirect = Some(append);
+            }
+            '<' => {
+                tokenizer.finish_word();
+                tokenizer.input_redirect = true;
+            }
+            '*' | '?' | '[' => {
+                tokenizer.in_word = true;
+                tokenizer.pattern = true;
+                tokenizer.current.push(c);
+            }
+            _ => {
+                tokenizer.in_word = true;
+                tokenizer.current.push(c);
+            }
+        }
+    }
+    tokenizer.finish_word();
+
+    // A redirection whose target was split off (e.g. 2>&1) has no file
+    if let Some(append) = tokenizer.redirect {
+        tokenizer.tokens.push(Token::Redirect {
+            append,
+            target: None,
+        });
+    }
+
+    tokenizer.tokens
+}
+
+#[derive(Default)]
+struct Tokenizer {
+    tokens: Vec<Token>,
+    current: String,
+    in_word: bool,
+    pattern: bool,
+    /// Set after `>` or `>>`, the next word is the file written to
+    redirect: Option<bool>,
+    /// Set after `<`, the next word is a file that is only read
+    input_redirect: bool,
+}
+
+impl Tokenizer {
+    fn finish_word(&mut self) {
+        if !self.in_word {
+            return;
+        }
+
+        let text = std::mem::take(&mut self.current);
+        if let Some(append) = self.redirect.take() {
+            self.tokens.push(Token::Redirect {
+                append,
+                target: Some(text),
+            });
+        } else if self.input_redirect {
+            self.input_redirect = false;
+        } else {
+            self.tokens.push(Token::Word {
+                text,
+                pattern: self.pattern,
+            });
+        }
+
+        self.pattern = false;
+        self.in_word = false;
+    }
+}
+
+/// Returns the arguments that are not options, honouring `--`
+fn positional(args: &[Word]) -> Vec<&Word> {
+    let mut result = Vec::new();
+    let mut options_done = false;
+
+    for arg in args {
+        if !options_done && arg.text == "--" {
+            options_done = true;
+        } else if options_done || !arg.text.starts_with('-') || arg.text == "-" {
+            result.push(arg);
+        }
+    }
+
+    result
+}
+
+fn mv_targets(args: &[Word], cwd: &Path) -> Vec<Target> {
+    let mut paths = expand_all(positional(args), cwd);
+
+    // mv -t DIR SOURCE... moves everything into DIR
+    let target_dir = args
+        .iter()
+        .position(|a| a.text == "-t" || a.text == "--target-directory")
+        .and_then(|i| args.get(i + 1))
+        .map(|w| expand_tilde(&w.text))
+        .or_else(|| {
+            args.iter()
+                .find_map(|a| a.text.strip_prefix("--target-directory="))
+                .map(expand_tilde)
+        });
+
+    let destination = match target_dir {
+        Some(dir) => {
+            paths.retain(|p| *p != dir);
+            dir
+        }
+        None => match paths.pop() {
+            Some(destination) => destination,
+            None => return Vec::new(),
+        },
+    };
+
+    let mut targets = Vec::new();
+    let into_dir = cwd.join(&destination).is_dir();
+
+    for source in paths {
+        if !cwd.join(&source).exists() {
+            continue;
+        }
+
+        let overwritten = if into_dir {
+            source.file_name().map(|name| destination.join(name))
+        } else {
+            Some(destination.clone())
+        };
+
+        targets.push(Target {
+            action: Action::Move,
+            path: source,
+            destination: overwritten.clone(),
+        });
+
+        if let Some(overwritten) = overwritten {
+            if cwd.join(&overwritten).is_file() {
+                targets.push(Target {
+                    action: Action::Overwrite,
+                    path: overwritten,
+                    destination: None,
+                });
+            }
+        }
+    }
+
+    targets
+}
+
+fn sed_targets(args: &[Word], cwd: &Path) -> Vec<Target> {
+    let in_place = args.iter().any(|a| match a.text.strip_prefix("--") {
+        Some(long) => long == "in-place" || long.starts_with("in-place="),
+        // Short options may be combined, as in -ni or -i.bak
+        None => a.text.starts_with('-') && a.text.contains('i'),
+    });
+    if !in_place {
+        return Vec::new();
+    }
+
+    // Without -e or -f the first positional argument is the script
+    let mut has_script_option = false;
+    let mut operands = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.text.as_str() {
+            "-e" | "-f" | "--expression" | "--file" => {
+                has_script_option = true;
+                iter.next();
+            }
+            text if text.starts_with("--expression=") || text.starts_with("--file=") => {
+                has_script_option = true;
+            }
+            text if text.starts_with('-') && text != "-" => {}
+            _ => operands.push(arg),
+        }
+    }
+    if !has_script_option && !operands.is_empty() {
+        operands.remove(0);
+    }
+
+    expand_all(operands, cwd)
+        .into_iter()
+        .filter(|path| cwd.join(path).is_file())
+        .map(|path| Target {
+            action: Action::Modify,
+            path,
+            destination: None,
+        })
+        .collect()
+}
+
+/// Runs `find` with `-delete` and `-exec rm ...` replaced by `-print` to list
+/// the files it would remove
+fn find_targets(args: &[Word], cwd: &Path) -> Vec<Target> {
+    let mut find_args = Vec::new();
+    let mut deletes = false;
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.text.as_str() {
+            "-delete" => {
+                deletes = true;
+                find_args.push("-print".to_string());
+            }
+            "-exec" | "-execdir" => {
+                let program = iter.next().map(|w| w.text.as_str()).unwrap_or_default();
+                if program != "rm" && program != "/bin/rm" {
+                    // Running arbitrary commands is not something a preview can do
+                    return Vec::new();
+                }
+                for arg in iter.by_ref() {
+                    if arg.text == ";" || arg.text == "+" {
+                        break;
+                    }
+                }
+                deletes = true;
+                find_args.push("-print".to_string());
+            }
+            "-ok" | "-okdir" | "-fprint" | "-fprint0" | "-fprintf" | "-fls" => {
+                return Vec::new();
+            }
+            _ if arg.pattern => find_args.extend(
+                expand(arg, cwd)
+                    .into_iter()
+                    .map(|p| p.display().to_string()),
+            ),
+            _ => find_args.push(arg.text.clone()),
+        }
+    }
+
+    if !deletes {
+        return Vec::new();
+    }
+
+    let output = match Command::new("find")
+        .args(&find_args)
+        .current_dir(cwd)
+        .output()
+    {
+        Ok(output) => output,
+        Err(_) => return Vec::new(),
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| Target {
+            action: Action::Delete,
+            path: PathBuf::from(line),
+            destination: None,
+        })
+        .collect()
+}
+
+fn expand_all(words: Vec<&Word>, cwd: &Path) -> Vec<PathBuf> {
+    words.into_iter().flat_map(|w| expand(w, cwd)).collect()
+}
+
+/// Expands a word the way the shell would, returning it unchanged when a glob
+/// matches nothing
+fn expand(word: &Word, cwd: &Path) -> Vec<PathBuf> {
+    let path = expand_tilde(&word.text);
+    if !word.pattern {
+        return vec![path];
+    }
+
+    let absolute = path.is_absolute();
+    let pattern = if absolute {
+        path.display().to_string()
+    } else {
+        format!(
+            "{}/{}",
+            glob::Pattern::escape(&cwd.display().to_string()),
+            path.display()
+        )
+    };
+
+    let matches: Vec<PathBuf> = match glob::glob(&pattern) {
+        Ok(paths) => paths
+            .filter_map(|p| p.ok())
+            .map(|p| {
+                if absolute {
+                    p
+                } else {
+                    p.strip_prefix(cwd).map(Path::to_path_buf).unwrap_or(p)
+                }
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    if matches.is_empty() {
+        vec![path]
+    } else {
+        matches
+    }
+}
+
+fn expand_tilde(text: &str) -> PathBuf {
+    match text.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir()
+            .map(|home| home.join(rest))
+            .unwrap_or_else(|| PathBuf::from(text)),
+        None => PathBuf::from(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn setup() -> tempfile::TempDir {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.log"), "a").unwrap();
+        fs::write(dir.path().join("b.log"), "b").unwrap();
+        fs::write(dir.path().join("notes.txt"), "n").unwrap();
+        fs::create_dir(dir.path().join("backup")).unwrap();
+        fs::write(dir.path().join("backup/notes.txt"), "old").unwrap();
+        dir
+    }
+
+    fn paths(targets: &[Target], action: Action) -> Vec<String> {
+        targets
+            .iter()
+            .filter(|t| t.action == action)
+            .map(|t| t.path.display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("grep 'a b' \"c\\\"d\" e\\ f *.rs 2> err.txt >> log"),
+            vec![
+                Token::Word {
+                    text: "grep".to_string(),
+                    pattern: false
+                },
+                Token::Word {
+                    text: "a b".to_string(),
+                    pattern: false
+                },
+                Token::Word {
+                    text: "c\"d".to_string(),
+                    pattern: false
+                },
+                Token::Word {
+                    text: "e f".to_string(),
+                    pattern: false
+                },
+                Token::Word {
+                    text: "*.rs".to_string(),
+                    pattern: true
+                },
+                Token::Redirect {
+                    append: false,
+                    target: Some("err.txt".to_string())
+                },
+                Token::Redirect {
+                    append: true,
+                    target: Some("log".to_string())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rm_expands_globs() {
+        let dir = setup();
+        let targets = affected_files("rm -f *.log missing.log", dir.path());
+        assert_eq!(paths(&targets, Action::Delete), vec!["a.log", "b.log"]);
+
+        // Quoted globs are not expanded by the shell
+        let targets = affected_files("rm '*.log'", dir.path());
+        assert!(targets.is_empty());
+
+        let targets = affected_files("sudo rm -rf backup", dir.path());
+        assert_eq!(paths(&targets, Action::Delete), vec!["backup"]);
+    }
+
+    #[test]
+    fn test_wrapped_commands() {
+        let dir = setup();
+        for command in [
+            "sudo -u bob rm a.log",
+            "timeout 5 rm a.log",
+            "xargs -0 rm a.log",
+            "LC_ALL=C nice -n 10 env -u HOME rm a.log",
+        ] {
+            let targets = affected_files(command, dir.path());
+            assert_eq!(
+                paths(&targets, Action::Delete),
+                vec!["a.log"],
+                "{}",
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn test_mv_reports_overwritten_files() {
+        let dir = setup();
+        let targets = affected_files("mv notes.txt a.log backup/", dir.path());
+        assert_eq!(paths(&targets, Action::Move), vec!["notes.txt", "a.log"]);
+        assert_eq!(paths(&targets, Action::Overwrite), vec!["backup/notes.txt"]);
+
+        let targets = affected_files("mv a.log b.log", dir.path());
+        assert_eq!(paths(&targets, Action::Move), vec!["a.log"]);
+        assert_eq!(paths(&targets, Action::Overwrite), vec!["b.log"]);
+    }
+
+    #[test]
+    fn test_sed_in_place() {
+        let dir = setup();
+        let targets = affected_files("sed -i 's/a/b/' *.log", dir.path());
+        assert_eq!(paths(&targets, Action::Modify), vec!["a.log", "b.log"]);
+
+        let targets = affected_files("sed -e 's/a/b/' -i.bak notes.txt", dir.path());
+        assert_eq!(paths(&targets, Action::Modify), vec!["notes.txt"]);
+
+        let targets = affected_files("sed 's/a/b/' notes.txt", dir.path());
+        assert!(targets.is_empty());
+    }
+
+    #[test]
+    fn test_redirections() {
+        let dir = setup();
+        let targets = affected_files(
+            "ls > notes.txt; echo hi >> a.log; echo x > new.txt 2>&1 > /dev/null",
+            dir.path(),
+        );
+        assert_eq!(paths(&targets, Action::Overwrite), vec!["notes.txt"]);
+        assert_eq!(paths(&targets, Action::Modify), vec!["a.log"]);
+        assert_eq!(paths(&targets, Action::Create), vec!["new.txt"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_find_delete() {
+        let dir = setup();
+        let mut targets = affected_files("find . -name \"*.log\" -delete", dir.path());
+        targets.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(paths(&targets, Action::Delete), vec!["./a.log", "./b.log"]);
+
+        let targets = affected_files("find . -name notes.txt -exec rm {} \\;", dir.path());
+        assert_eq!(targets.len(), 2);
+
+        // Other -exec commands are not previewed
+        let targets = affected_files("find . -exec chmod 600 {} +", dir.path());
+        assert!(targets.is_empty());
+
+        // Without -delete nothing is touched
+        let targets = affected_files("find . -name \"*.log\"", dir.path());
+        assert!(targets.is_empty());
+
+        assert!(dir.path().join("a.log").exists());
+    }
+
+    #[test]
+    fn test_ignores_harmless_commands() {
+        let dir = setup();
+        assert!(affected_files("ls -la", dir.path()).is_empty());
+        assert!(affected_files("cat *.log | grep error", dir.path()).is_empty());
+    }
+}
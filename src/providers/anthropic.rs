@@ -3,7 +3,7 @@ use async_trait::async_trait;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use super::Provider;
+use super::{http_client, Message, Provider};
 use crate::config::Config;
 
 const ANTHROPIC_API_URL: &str = "https://api.anthropic.com/v1/messages";
@@ -12,12 +12,12 @@ const ANTHROPIC_API_URL: &str = "https://api.anthropic.com/v1/messages";
 struct AnthropicRequest {
     model: String,
     max_tokens: u32,
-    messages: Vec<Message>,
+    messages: Vec<RequestMessage>,
     system: String,
 }
 
 #[derive(Debug, Serialize)]
-struct Message {
+struct RequestMessage {
     role: String,
     content: String,
 }
@@ -42,7 +42,7 @@ pub struct AnthropicProvider {
 impl AnthropicProvider {
     pub fn new(model: String, auth_token: String, config: Config) -> Self {
         Self {
-            client: Client::new(),
+            client: http_client(),
             model,
             auth_token,
             config,
@@ -52,14 +52,17 @@ impl AnthropicProvider {
 
 #[async_trait]
 impl Provider for AnthropicProvider {
-    async fn get_command_suggestion(&self, prompt: &str, system_prompt: String) -> Result<String> {
+    async fn complete(&self, messages: &[Message], system_prompt: String) -> Result<String> {
         let request = AnthropicRequest {
             model: self.model.clone(),
             max_tokens: self.config.max_tokens() as u32,
-            messages: vec![Message {
-                role: "user".to_string(),
-                content: prompt.to_string(),
-            }],
+            messages: messages
+                .iter()
+                .map(|message| RequestMessage {
+                    role: message.role.as_str().to_string(),
+                    content: message.content.clone(),
+                })
+                .collect(),
             system: system_prompt,
         };
 
@@ -2,7 +2,7 @@ use anyhow::Result;
 use async_trait::async_trait;
 use std::collections::HashMap;
 
-use super::Provider;
+use super::{Message, Provider, Role};
 use crate::config::Config;
 
 pub struct MockProvider {
@@ -27,7 +27,15 @@ impl MockProvider {
 
 #[async_trait]
 impl Provider for MockProvider {
-    async fn get_command_suggestion(&self, prompt: &str, _system_prompt: String) -> Result<String> {
+    async fn complete(&self, messages: &[Message], _system_prompt: String) -> Result<String> {
+        // Answer the latest user message
+        let prompt = messages
+            .iter()
+            .rev()
+            .find(|message| message.role == Role::User)
+            .map(|message| message.content.as_str())
+            .unwrap_or_default();
+
         // Try to find an exact match
         if let Some(response) = self.responses.get(prompt) {
             return Ok(response.clone());
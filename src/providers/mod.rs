@@ -1,5 +1,7 @@
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use reqwest::Client;
+use std::sync::OnceLock;
 
 mod anthropic;
 #[cfg(test)]
@@ -13,11 +15,61 @@ pub use openai::OpenAIProvider;
 
 use crate::config::Config;
 
+/// Who wrote a message in a conversation with the model
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+}
+
+impl Message {
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::User,
+            content: content.into(),
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::Assistant,
+            content: content.into(),
+        }
+    }
+}
+
 #[async_trait]
 pub trait Provider: Send + Sync {
-    async fn get_command_suggestion(&self, prompt: &str, system_prompt: String) -> Result<String>;
+    /// Sends a conversation, which starts and ends with a user message, and
+    /// returns the model's reply
+    async fn complete(&self, messages: &[Message], system_prompt: String) -> Result<String>;
 }
 
+/// One HTTP client is shared by every provider, so that an interactive
+/// session keeps its connections open between requests
+fn http_client() -> Client {
+    static CLIENT: OnceLock<Client> = OnceLock::new();
+    CLIENT.get_or_init(Client::new).clone()
+}
+
+/// The providers a model can use
+pub const PROVIDERS: [&str; 2] = ["openai", "anthropic"];
+
 pub fn create_provider(model_name: &str, config: &Config) -> Result<Box<dyn Provider>> {
     let model_config = config
         .models()
@@ -28,7 +80,7 @@ pub fn create_provider(model_name: &str, config: &Config) -> Result<Box<dyn Prov
         .model
         .clone()
         .unwrap_or_else(|| model_name.to_string());
-    let auth_token = config.get_provider_auth_token(&model_config.provider, model_config);
+    let auth_token = config.get_provider_auth_token(&model_config.provider, model_config)?;
 
     match model_config.provider.as_str() {
         "openai" => Ok(Box::new(OpenAIProvider::new(
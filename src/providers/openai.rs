@@ -3,7 +3,7 @@ use async_trait::async_trait;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use super::Provider;
+use super::{http_client, Message, Provider};
 use crate::config::Config;
 
 const OPENAI_API_URL: &str = "https://api.openai.com/v1/chat/completions";
@@ -11,13 +11,13 @@ const OPENAI_API_URL: &str = "https://api.openai.com/v1/chat/completions";
 #[derive(Debug, Serialize)]
 struct OpenAIRequest {
     model: String,
-    messages: Vec<Message>,
+    messages: Vec<RequestMessage>,
     temperature: f32,
     max_tokens: u32,
 }
 
 #[derive(Debug, Serialize)]
-struct Message {
+struct RequestMessage {
     role: String,
     content: String,
 }
@@ -47,7 +47,7 @@ pub struct OpenAIProvider {
 impl OpenAIProvider {
     pub fn new(model: String, auth_token: String, config: Config) -> Self {
         Self {
-            client: Client::new(),
+            client: http_client(),
             model,
             auth_token,
             config,
@@ -57,19 +57,19 @@ impl OpenAIProvider {
 
 #[async_trait]
 impl Provider for OpenAIProvider {
-    async fn get_command_suggestion(&self, prompt: &str, system_prompt: String) -> Result<String> {
+    async fn complete(&self, messages: &[Message], system_prompt: String) -> Result<String> {
+        let system = RequestMessage {
+            role: "system".to_string(),
+            content: system_prompt,
+        };
+        let conversation = messages.iter().map(|message| RequestMessage {
+            role: message.role.as_str().to_string(),
+            content: message.content.clone(),
+        });
+
         let request = OpenAIRequest {
             model: self.model.clone(),
-            messages: vec![
-                Message {
-                    role: "system".to_string(),
-                    content: system_prompt,
-                },
-                Message {
-                    role: "user".to_string(),
-                    content: prompt.to_string(),
-                },
-            ],
+            messages: std::iter::once(system).chain(conversation).collect(),
             temperature: self.config.temperature(),
             max_tokens: self.config.max_tokens() as u32,
         };
@@ -0,0 +1,116 @@
+use crate::providers::{Message, Role};
+use crate::translate::SHELLS;
+
+/// Earlier messages are forgotten so that requests stay small
+const MAX_MEMORY_MESSAGES: usize = 20;
+
+/// A `/` command typed in an interactive session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetaCommand {
+    /// Show the current model, or switch to another one
+    Model(Option<String>),
+    /// Show the current shell, or switch to another one
+    Shell(Option<String>),
+    /// Explain a command, or the last one suggested
+    Explain(Option<String>),
+    History,
+    Reset,
+    Help,
+    Exit,
+}
+
+/// Parses a line starting with `/`. Returns `None` for anything else, which
+/// is a request for a command.
+pub fn parse_meta_command(line: &str) -> Option<Result<MetaCommand, String>> {
+    let line = line.trim().strip_prefix('/')?;
+    let (name, argument) = match line.split_once(char::is_whitespace) {
+        Some((name, argument)) => (name, Some(argument.trim().to_string())),
+        None => (line, None),
+    };
+    let argument = argument.filter(|a| !a.is_empty());
+
+    Some(match name {
+        "model" => Ok(MetaCommand::Model(argument)),
+        "shell" => match argument {
+            Some(shell) if !SHELLS.contains(&shell.as_str()) => Err(format!(
+                "Unknown shell '{}', expected one of {}",
+                shell,
+                SHELLS.join(", ")
+            )),
+            shell => Ok(MetaCommand::Shell(shell)),
+        },
+        "explain" => Ok(MetaCommand::Explain(argument)),
+        "history" => Ok(MetaCommand::History),
+        "reset" => Ok(MetaCommand::Reset),
+        "help" => Ok(MetaCommand::Help),
+        "exit" | "quit" => Ok(MetaCommand::Exit),
+        _ => Err(format!("Unknown command /{}. Type /help for a list.", name)),
+    })
+}
+
+pub fn print_help() {
+    println!("Type a request to get a command, or:");
+    println!("  /model [NAME]       show the model, or switch to NAME");
+    println!("  /shell [NAME]       show the shell, or switch to NAME");
+    println!("  /explain [COMMAND]  explain COMMAND, or the last suggested command");
+    println!("  /history            show the command history");
+    println!("  /reset              forget the conversation so far");
+    println!("  /exit               leave (or press Ctrl-D)");
+}
+
+/// Drops the oldest exchanges once the conversation gets long, keeping it
+/// starting with a user message
+pub fn trim_memory(memory: &mut Vec<Message>) {
+    if memory.len() <= MAX_MEMORY_MESSAGES {
+        return;
+    }
+
+    let mut start = memory.len() - MAX_MEMORY_MESSAGES;
+    while start < memory.len() && memory[start].role != Role::User {
+        start += 1;
+    }
+    memory.drain(..start);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_meta_command() {
+        assert_eq!(parse_meta_command("list all files"), None);
+        assert_eq!(
+            parse_meta_command("/model claude"),
+            Some(Ok(MetaCommand::Model(Some("claude".to_string()))))
+        );
+        assert_eq!(
+            parse_meta_command("/model"),
+            Some(Ok(MetaCommand::Model(None)))
+        );
+        assert_eq!(
+            parse_meta_command("/explain  tar -xzf a.tgz "),
+            Some(Ok(MetaCommand::Explain(Some("tar -xzf a.tgz".to_string()))))
+        );
+        assert_eq!(
+            parse_meta_command("/shell fish"),
+            Some(Ok(MetaCommand::Shell(Some("fish".to_string()))))
+        );
+        assert!(matches!(parse_meta_command("/shell ./evil"), Some(Err(_))));
+        assert_eq!(parse_meta_command("/quit"), Some(Ok(MetaCommand::Exit)));
+        assert!(matches!(parse_meta_command("/nope"), Some(Err(_))));
+    }
+
+    #[test]
+    fn test_trim_memory() {
+        let mut memory = Vec::new();
+        for i in 0..15 {
+            memory.push(Message::user(format!("request {}", i)));
+            memory.push(Message::assistant(format!("command {}", i)));
+        }
+
+        trim_memory(&mut memory);
+        assert_eq!(memory.len(), MAX_MEMORY_MESSAGES);
+        assert_eq!(memory[0], Message::user("request 5"));
+        assert_eq!(memory.last().unwrap(), &Message::assistant("command 14"));
+    }
+}
@@ -0,0 +1,394 @@
+/// Programs that run another command with elevated privileges
+const ESCALATION_TOOLS: &[&str] = &["sudo", "doas", "pkexec"];
+
+/// Programs that run the command given as their arguments, with their
+/// options that take a value in the next word and the number of operands
+/// before the command (the duration of `timeout 5 cmd`)
+#[rustfmt::skip]
+const WRAPPERS: &[(&str, &[&str], usize)] = &[
+    ("env", &["-u", "--unset", "-C", "--chdir", "-S", "--split-string"], 0),
+    ("nohup", &[], 0),
+    ("time", &["-f", "--format", "-o", "--output"], 0),
+    ("command", &[], 0),
+    ("exec", &["-a"], 0),
+    ("nice", &["-n", "--adjustment"], 0),
+    ("xargs", &["-n", "--max-args", "-I", "-L", "--max-lines", "-P", "--max-procs",
+                "-s", "--max-chars", "-d", "--delimiter", "-E", "-a", "--arg-file"], 0),
+    ("timeout", &["-s", "--signal", "-k", "--kill-after"], 1),
+    ("stdbuf", &["-i", "-o", "-e", "--input", "--output", "--error"], 0),
+    ("ionice", &["-c", "--class", "-n", "--classdata"], 0),
+    ("setsid", &[], 0),
+];
+
+/// Privilege escalation tools with their options that take a value, skipped
+/// like wrappers to find the command they run as another user
+#[rustfmt::skip]
+const ESCALATION_WRAPPERS: &[(&str, &[&str], usize)] = &[
+    ("sudo", &["-u", "--user", "-g", "--group", "-C", "--close-from", "-D", "--chdir",
+               "-h", "--host", "-p", "--prompt", "-r", "--role", "-t", "--type",
+               "-T", "--command-timeout", "-U", "--other-user"], 0),
+    ("doas", &["-u", "-C"], 0),
+    ("pkexec", &["--user"], 0),
+];
+
+/// Shells whose `-c` argument is a command line of its own
+const SHELLS: &[&str] = &["sh", "bash", "zsh", "dash", "ksh", "fish"];
+
+/// Returns the name of the privilege escalation tool used by a command, if any.
+///
+/// This checks the program that starts every pipeline stage, list element or
+/// command substitution, so `ls | sudo tee /etc/x` and `echo $(doas cat f)` are
+/// both caught, while `grep sudo /var/log/auth.log` is not. The command line
+/// given to `sh -c` and other shells is checked as well.
+pub fn privilege_escalation(command: &str) -> Option<&'static str> {
+    split_simple_commands(command).iter().find_map(|segment| {
+        let words = executed_words(segment);
+        escalation_in_words(&words).or_else(|| shell_command(&words).and_then(privilege_escalation))
+    })
+}
+
+/// Builds a follow-up prompt asking the model to avoid elevated privileges
+pub fn unprivileged_prompt(prompt: &str, command: &str) -> String {
+    format!(
+        "{}\n\nYou previously suggested `{}`, which requires elevated privileges. \
+         Suggest an alternative that does not use sudo, doas, su or pkexec, \
+         for example by working on files the user owns.",
+        prompt.trim(),
+        command
+    )
+}
+
+/// Splits a command line on shell control operators (`;`, `&`, `|`, newlines)
+/// and the start of subshells, command groups or command substitutions
+pub fn split_simple_commands(command: &str) -> Vec<String> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match quote {
+            Some('\'') => {
+                if c == '\'' {
+                    quote = None;
+                }
+                current.push(c);
+            }
+            Some(q) => {
+                // Command substitutions inside double quotes still run commands
+                if (c == '(' && current.ends_with('$')) || c == '`' {
+                    segments.push(std::mem::take(&mut current));
+                } else {
+                    if c == q {
+                        quote = None;
+                    } else if c == '\\' {
+                        current.push(c);
+                        if let Some(next) = chars.next() {
+                            current.push(next);
+                        }
+                        continue;
+                    }
+                    current.push(c);
+                }
+            }
+            None => match c {
+                '\'' | '"' => {
+                    quote = Some(c);
+                    current.push(c);
+                }
+                '\\' => {
+                    current.push(c);
+                    if let Some(next) = chars.next() {
+                        current.push(next);
+                    }
+                }
+                ';' | '&' | '|' | '\n' | '(' | ')' | '`' => {
+                    segments.push(std::mem::take(&mut current));
+                }
+                // Braces only group commands when they stand alone as a word,
+                // unlike the {} placeholder of find -exec
+                '{' | '}'
+                    if current.chars().last().is_none_or(char::is_whitespace)
+                        && chars.peek().is_none_or(|c| c.is_whitespace()) =>
+                {
+                    segments.push(std::mem::take(&mut current));
+                }
+                _ => current.push(c),
+            },
+        }
+    }
+    segments.push(current);
+
+    segments
+        .into_iter()
+        .map(|s| s.trim().trim_end_matches('$').trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// The name of the program a simple command runs, e.g. `rm` for
+/// `xargs -n 1 rm`
+pub(crate) fn executed_program(segment: &str) -> Option<String> {
+    executed_words(segment)
+        .first()
+        .map(|word| program_name(word).to_string())
+}
+
+/// The words of a simple command from the program it runs on, skipping
+/// environment assignments (FOO=bar cmd) and wrapper programs along with
+/// their options and operands
+pub(crate) fn executed_words(segment: &str) -> Vec<String> {
+    let mut words = shell_words(segment);
+    let start = skip_wrappers(&words, WRAPPERS);
+    words.split_off(start)
+}
+
+/// Where the command that `sudo`, `doas` or `pkexec` runs starts in `words`,
+/// also skipping assignments and wrappers: 3 for `sudo -u bob rm x`
+pub(crate) fn privileged_command_start(words: &[String]) -> usize {
+    let all: Vec<_> = WRAPPERS
+        .iter()
+        .chain(ESCALATION_WRAPPERS)
+        .copied()
+        .collect();
+    skip_wrappers(words, &all)
+}
+
+/// The index of the first word after assignments and `wrappers` with their
+/// options and operands
+fn skip_wrappers(words: &[String], wrappers: &[(&str, &[&str], usize)]) -> usize {
+    let mut start = 0;
+    while words.get(start).is_some_and(|word| is_assignment(word)) {
+        start += 1;
+    }
+
+    while let Some((_, value_options, operands)) = words.get(start).and_then(|word| {
+        wrappers
+            .iter()
+            .find(|(name, ..)| *name == program_name(word))
+    }) {
+        start += 1;
+        while let Some(word) = words.get(start) {
+            if word == "--" {
+                start += 1;
+                break;
+            }
+            if is_assignment(word) {
+                start += 1;
+            } else if word.starts_with('-') && word != "-" {
+                start += if value_options.contains(&word.as_str()) {
+                    2
+                } else {
+                    1
+                };
+            } else {
+                break;
+            }
+        }
+        start += operands;
+    }
+
+    start.min(words.len())
+}
+
+/// The command line a shell is asked to run with `-c`, as in
+/// `sh -c 'apt update && sudo apt upgrade'`
+fn shell_command(words: &[String]) -> Option<&str> {
+    let (program, args) = words.split_first()?;
+    if !SHELLS.contains(&program_name(program)) {
+        return None;
+    }
+
+    // The flag may be combined with others, as in `bash -lc`
+    let flag = args
+        .iter()
+        .position(|arg| arg.starts_with('-') && !arg.starts_with("--") && arg.contains('c'))?;
+    args[flag + 1..]
+        .iter()
+        .find(|arg| !arg.starts_with('-'))
+        .map(String::as_str)
+}
+
+fn escalation_in_words(words: &[String]) -> Option<&'static str> {
+    let (program, args) = words.split_first()?;
+    let program = program_name(program);
+
+    if let Some(tool) = ESCALATION_TOOLS.iter().find(|tool| **tool == program) {
+        return Some(tool);
+    }
+
+    if program == "su" && args.iter().any(|w| w == "-c" || w.starts_with("--command")) {
+        return Some("su");
+    }
+
+    None
+}
+
+/// Splits a simple command into words the way the shell does, removing
+/// quotes and backslashes
+fn shell_words(segment: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut chars = segment.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => word.extend(chars.next()),
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, '\\') => {
+                word.extend(chars.next());
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+/// Strips any directory from a program path, e.g. `/usr/bin/sudo` -> `sudo`
+fn program_name(word: &str) -> &str {
+    let word = word.trim_matches(|c| c == '"' || c == '\'');
+    word.rsplit('/').next().unwrap_or(word)
+}
+
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_escalation_tools() {
+        assert_eq!(privilege_escalation("sudo apt update"), Some("sudo"));
+        assert_eq!(
+            privilege_escalation("doas rc-service nginx restart"),
+            Some("doas")
+        );
+        assert_eq!(privilege_escalation("pkexec visudo"), Some("pkexec"));
+        assert_eq!(
+            privilege_escalation("su -c 'systemctl restart x'"),
+            Some("su")
+        );
+        assert_eq!(privilege_escalation("su root --command=id"), Some("su"));
+        assert_eq!(privilege_escalation("/usr/bin/sudo ls /root"), Some("sudo"));
+    }
+
+    #[test]
+    fn test_detects_escalation_anywhere_in_pipeline() {
+        assert_eq!(
+            privilege_escalation("echo 1 | sudo tee /proc/sys/vm/drop_caches"),
+            Some("sudo")
+        );
+        assert_eq!(
+            privilege_escalation("make && sudo make install"),
+            Some("sudo")
+        );
+        assert_eq!(privilege_escalation("cat $(sudo ls /root)"), Some("sudo"));
+        assert_eq!(privilege_escalation("echo \"`doas id`\""), Some("doas"));
+        assert_eq!(
+            privilege_escalation("find . -name x | xargs -0 sudo rm"),
+            Some("sudo")
+        );
+        assert_eq!(
+            privilege_escalation("DEBIAN_FRONTEND=noninteractive sudo apt -y upgrade"),
+            Some("sudo")
+        );
+    }
+
+    #[test]
+    fn test_privilege_escalation() {
+        assert_eq!(
+            privilege_escalation("nice -n 10 sudo make install"),
+            Some("sudo")
+        );
+        assert_eq!(privilege_escalation("env -u HOME sudo id"), Some("sudo"));
+        assert_eq!(privilege_escalation("timeout 5 sudo reboot"), Some("sudo"));
+        assert_eq!(privilege_escalation("xargs -I {} sudo rm {}"), Some("sudo"));
+        assert_eq!(
+            privilege_escalation("sh -c 'apt update && sudo apt upgrade'"),
+            Some("sudo")
+        );
+        assert_eq!(privilege_escalation("bash -lc \"doas id\""), Some("doas"));
+        assert_eq!(
+            privilege_escalation("sh -c \"bash -c 'pkexec ls'\""),
+            Some("pkexec")
+        );
+        assert_eq!(privilege_escalation("sh -c 'echo sudo'"), None);
+        assert_eq!(privilege_escalation("timeout 5 make"), None);
+    }
+
+    #[test]
+    fn test_executed_program() {
+        assert_eq!(
+            executed_program("LC_ALL=C sort -u").as_deref(),
+            Some("sort")
+        );
+        assert_eq!(
+            executed_program("xargs -0 /bin/rm -f").as_deref(),
+            Some("rm")
+        );
+        assert_eq!(executed_program("xargs -n 1 rm").as_deref(), Some("rm"));
+        assert_eq!(executed_program("xargs -I {} rm {}").as_deref(), Some("rm"));
+        assert_eq!(
+            executed_program("timeout -s KILL 5 make").as_deref(),
+            Some("make")
+        );
+        assert_eq!(executed_program("'/bin/cat' a").as_deref(), Some("cat"));
+        assert_eq!(executed_program(""), None);
+    }
+
+    #[test]
+    fn test_split_simple_commands() {
+        assert_eq!(
+            split_simple_commands("a | b && { c; d; } || echo \"$(e)\""),
+            vec!["a", "b", "c", "d", "echo \"", "e)\""]
+        );
+        assert_eq!(
+            split_simple_commands("find . -exec rm {} \\; ; echo 'x;y'"),
+            vec!["find . -exec rm {} \\;", "echo 'x;y'"]
+        );
+    }
+
+    #[test]
+    fn test_ignores_mentions_in_arguments() {
+        assert_eq!(privilege_escalation("grep sudo /var/log/auth.log"), None);
+        assert_eq!(privilege_escalation("echo 'run sudo later'"), None);
+        assert_eq!(privilege_escalation("man su"), None);
+        assert_eq!(privilege_escalation("su"), None);
+        assert_eq!(privilege_escalation("ls -la"), None);
+        assert_eq!(privilege_escalation(""), None);
+    }
+
+    #[test]
+    fn test_unprivileged_prompt() {
+        let prompt = unprivileged_prompt("install ripgrep\n", "sudo apt install ripgrep");
+        assert!(prompt.starts_with("install ripgrep\n\n"));
+        assert!(prompt.contains("`sudo apt install ripgrep`"));
+    }
+}
@@ -0,0 +1,300 @@
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::HaiError;
+
+/// Maximum number of changed files listed in the report
+const MAX_LISTED_CHANGES: usize = 20;
+
+/// The outcome of running a command in the sandbox
+#[derive(Debug)]
+pub struct SandboxRun {
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub changes: Vec<Change>,
+}
+
+/// A file in the working directory that the command changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(PathBuf),
+    Removed(PathBuf),
+    Modified(PathBuf),
+}
+
+/// Runs a command with bubblewrap: the root file system is read-only, the
+/// network is unavailable, and the current directory is an overlay whose
+/// changes go to a throwaway directory. The real directory is never touched
+/// and nothing is copied up front.
+pub fn execute_sandboxed(command: &str, shell: &str) -> Result<SandboxRun> {
+    let help = Command::new("bwrap").arg("--help").output().map_err(|_| {
+        HaiError::command(
+            "Sandbox mode needs bubblewrap (bwrap), which was not found. \
+             Install it with your package manager to use --sandbox.",
+        )
+    })?;
+    if !String::from_utf8_lossy(&help.stdout).contains("--overlay-src") {
+        return Err(HaiError::command(
+            "Sandbox mode needs bubblewrap 0.10 or newer, for overlay mounts",
+        ));
+    }
+
+    let cwd = std::env::current_dir().context("Failed to get the current directory")?;
+
+    // overlayfs needs its upper and work directories on the same file system
+    let scratch = tempfile::tempdir().context("Failed to create sandbox directory")?;
+    let upper = scratch.path().join("upper");
+    let work = scratch.path().join("work");
+    fs::create_dir(&upper).context("Failed to create sandbox directory")?;
+    fs::create_dir(&work).context("Failed to create sandbox directory")?;
+
+    let flag = if matches!(shell, "powershell" | "pwsh") {
+        "-Command"
+    } else {
+        "-c"
+    };
+
+    let output = Command::new("bwrap")
+        .args(bwrap_args(&cwd, &upper, &work))
+        .arg(shell)
+        .arg(flag)
+        .arg(command)
+        .output()
+        .context("Failed to execute command in the sandbox")?;
+
+    Ok(SandboxRun {
+        exit_code: output.status.code(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        changes: overlay_changes(&cwd, &upper),
+    })
+}
+
+/// Prints the output of a sandboxed run and the files it changed
+pub fn print_report(run: &SandboxRun) {
+    println!(
+        "Sandbox run finished with exit code {}",
+        match run.exit_code {
+            Some(code) => code.to_string(),
+            None => "unknown".to_string(),
+        }
+    );
+
+    if !run.stdout.trim().is_empty() {
+        println!("Output:");
+        for line in run.stdout.lines() {
+            println!("  {}", line);
+        }
+    }
+    if !run.stderr.trim().is_empty() {
+        println!("Errors:");
+        for line in run.stderr.lines() {
+            println!("  {}", line);
+        }
+    }
+
+    if run.changes.is_empty() {
+        println!("No files in the current directory were changed");
+        return;
+    }
+
+    println!("Changes to the current directory:");
+    for change in run.changes.iter().take(MAX_LISTED_CHANGES) {
+        match change {
+            Change::Added(path) => println!("  + {}", path.display()),
+            Change::Removed(path) => println!("  - {}", path.display()),
+            Change::Modified(path) => println!("  ~ {}", path.display()),
+        }
+    }
+    if run.changes.len() > MAX_LISTED_CHANGES {
+        println!("  ... and {} more", run.changes.len() - MAX_LISTED_CHANGES);
+    }
+}
+
+fn bwrap_args(cwd: &Path, upper: &Path, work: &Path) -> Vec<OsString> {
+    let mut args: Vec<OsString> = [
+        "--ro-bind",
+        "/",
+        "/",
+        "--dev",
+        "/dev",
+        "--proc",
+        "/proc",
+        "--tmpfs",
+        "/tmp",
+    ]
+    .iter()
+    .map(OsString::from)
+    .collect();
+
+    // The overlay is mounted over the real directory so that paths in the
+    // command still work, and everything the command writes ends up in
+    // `upper`
+    args.push("--overlay-src".into());
+    args.push(cwd.into());
+    args.push("--overlay".into());
+    args.push(upper.into());
+    args.push(work.into());
+    args.push(cwd.into());
+    args.push("--chdir".into());
+    args.push(cwd.into());
+
+    for arg in ["--unshare-all", "--die-with-parent", "--new-session", "--"] {
+        args.push(arg.into());
+    }
+
+    args
+}
+
+/// Lists what the command changed from the overlay's upper directory, which
+/// holds every file it wrote and a whiteout for every file it removed
+fn overlay_changes(original: &Path, upper: &Path) -> Vec<Change> {
+    let mut changes = Vec::new();
+    collect_changes(original, upper, Path::new(""), &mut changes);
+    changes.sort_by(|a, b| change_path(a).cmp(change_path(b)));
+    changes
+}
+
+fn collect_changes(original: &Path, upper: &Path, relative: &Path, changes: &mut Vec<Change>) {
+    let Ok(entries) = fs::read_dir(upper.join(relative)) else {
+        return;
+    };
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let relative = relative.join(entry.file_name());
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let before = original.join(&relative);
+        let existed = fs::symlink_metadata(&before).is_ok();
+
+        if is_whiteout(&metadata) {
+            if before.is_dir() {
+                let mut removed = Vec::new();
+                list_files(&before, &relative, &mut removed);
+                changes.extend(removed.into_iter().map(Change::Removed));
+            } else {
+                changes.push(Change::Removed(relative));
+            }
+        } else if metadata.is_dir() {
+            if before.is_dir() {
+                collect_changes(original, upper, &relative, changes);
+            } else {
+                let mut added = Vec::new();
+                list_files(&upper.join(&relative), &relative, &mut added);
+                changes.extend(added.into_iter().map(Change::Added));
+            }
+        } else if !existed {
+            changes.push(Change::Added(relative));
+        } else if !same_contents(&before, &upper.join(&relative)) {
+            // Changing only the permissions or times also copies a file up
+            changes.push(Change::Modified(relative));
+        }
+    }
+}
+
+/// overlayfs marks a removed file with a character device numbered 0/0
+#[cfg(unix)]
+fn is_whiteout(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+    metadata.file_type().is_char_device() && metadata.rdev() == 0
+}
+
+#[cfg(not(unix))]
+fn is_whiteout(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+fn change_path(change: &Change) -> &Path {
+    match change {
+        Change::Added(path) | Change::Removed(path) | Change::Modified(path) => path,
+    }
+}
+
+/// Collects the relative path of every file and symlink below `dir`
+fn list_files(dir: &Path, relative: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let relative = relative.join(entry.file_name());
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => list_files(&entry.path(), &relative, files),
+            Ok(_) => files.push(relative),
+            Err(_) => {}
+        }
+    }
+}
+
+fn same_contents(a: &Path, b: &Path) -> bool {
+    if let (Ok(a_link), Ok(b_link)) = (fs::read_link(a), fs::read_link(b)) {
+        return a_link == b_link;
+    }
+
+    match (fs::read(a), fs::read(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_overlay_changes() {
+        let original = tempdir().unwrap();
+        fs::write(original.path().join("same.txt"), "same").unwrap();
+        fs::write(original.path().join("changed.txt"), "before").unwrap();
+        fs::write(original.path().join("touched.txt"), "touched").unwrap();
+        fs::create_dir(original.path().join("logs")).unwrap();
+        fs::write(original.path().join("logs/old.log"), "log").unwrap();
+
+        let upper = tempdir().unwrap();
+        assert!(overlay_changes(original.path(), upper.path()).is_empty());
+
+        fs::write(upper.path().join("changed.txt"), "after").unwrap();
+        fs::write(upper.path().join("touched.txt"), "touched").unwrap();
+        fs::create_dir(upper.path().join("logs")).unwrap();
+        fs::write(upper.path().join("logs/new.log"), "log").unwrap();
+        fs::create_dir_all(upper.path().join("build/out")).unwrap();
+        fs::write(upper.path().join("build/out/app"), "app").unwrap();
+
+        assert_eq!(
+            overlay_changes(original.path(), upper.path()),
+            vec![
+                Change::Added(PathBuf::from("build/out/app")),
+                Change::Modified(PathBuf::from("changed.txt")),
+                Change::Added(PathBuf::from("logs/new.log")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bwrap_args() {
+        let args = bwrap_args(
+            Path::new("/home/me/project"),
+            Path::new("/tmp/x/upper"),
+            Path::new("/tmp/x/work"),
+        );
+        let args: Vec<String> = args
+            .iter()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+
+        let joined = args.join(" ");
+        assert!(joined.starts_with("--ro-bind / /"));
+        assert!(joined.contains(
+            "--overlay-src /home/me/project --overlay /tmp/x/upper /tmp/x/work /home/me/project"
+        ));
+        assert!(joined.contains("--chdir /home/me/project"));
+        assert!(joined.contains("--unshare-all"));
+        assert_eq!(args.last().unwrap(), "--");
+    }
+}
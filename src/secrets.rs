@@ -0,0 +1,127 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+
+/// The service API keys are stored under in the keyring, with the provider
+/// as the user name
+#[cfg(target_os = "linux")]
+const KEYRING_SERVICE: &str = "hai";
+
+/// Whether API keys can be stored in the system keyring, which uses the
+/// Secret Service (GNOME Keyring, KWallet) and so is only supported on Linux
+pub const KEYRING_SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// Runs an `auth-token-command` such as `pass show openai` and returns the
+/// first line of its output. Each command only runs once per hai process, so
+/// that a password manager doesn't ask again for every request of an
+/// interactive session.
+pub fn token_from_command(command: &str) -> Result<String> {
+    static TOKENS: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+    let tokens = TOKENS.get_or_init(Default::default);
+    if let Some(token) = tokens.lock().unwrap().get(command) {
+        return Ok(token.clone());
+    }
+
+    let token = run_token_command(command)?;
+    tokens
+        .lock()
+        .unwrap()
+        .insert(command.to_string(), token.clone());
+    Ok(token)
+}
+
+fn run_token_command(command: &str) -> Result<String> {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    // stderr is left to the terminal, for passphrase prompts and errors
+    let output = Command::new(shell)
+        .args([flag, command])
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Failed to run auth-token-command `{}`", command))?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "auth-token-command `{}` failed with {}",
+            command,
+            output.status
+        ));
+    }
+
+    // `pass` prints the password on the first line and metadata after it
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let token = stdout.lines().next().unwrap_or_default().trim().to_string();
+    if token.is_empty() {
+        return Err(anyhow::anyhow!(
+            "auth-token-command `{}` printed nothing",
+            command
+        ));
+    }
+    Ok(token)
+}
+
+/// The API key stored in the keyring for a provider
+#[cfg(target_os = "linux")]
+pub fn token_from_keyring(provider: &str) -> Result<String> {
+    let provider = provider.to_string();
+    on_own_thread(move || {
+        keyring::Entry::new(KEYRING_SERVICE, &provider)?
+            .get_password()
+            .with_context(|| format!("Failed to read the {} API key from the keyring", provider))
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn token_from_keyring(_provider: &str) -> Result<String> {
+    Err(anyhow::anyhow!(
+        "auth-token-keyring is only supported on Linux"
+    ))
+}
+
+/// Stores the API key for a provider in the keyring, replacing any earlier
+/// one
+#[cfg(target_os = "linux")]
+pub fn store_in_keyring(provider: &str, token: &str) -> Result<()> {
+    let (provider, token) = (provider.to_string(), token.to_string());
+    on_own_thread(move || {
+        keyring::Entry::new(KEYRING_SERVICE, &provider)?
+            .set_password(&token)
+            .with_context(|| format!("Failed to store the {} API key in the keyring", provider))
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn store_in_keyring(_provider: &str, _token: &str) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "Storing API keys in the keyring is only supported on Linux"
+    ))
+}
+
+/// keyring talks to the Secret Service from a tokio runtime of its own, which
+/// can't be started on a thread that already runs hai's
+#[cfg(target_os = "linux")]
+fn on_own_thread<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    std::thread::spawn(f)
+        .join()
+        .map_err(|_| anyhow::anyhow!("The keyring lookup panicked"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_token_from_command() {
+        assert_eq!(
+            token_from_command("printf 'sk-123\\nlogin: me\\n'").unwrap(),
+            "sk-123"
+        );
+        assert!(token_from_command("exit 1").is_err());
+        assert!(token_from_command("true").is_err());
+    }
+}
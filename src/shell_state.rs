@@ -0,0 +1,273 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::utils;
+
+/// Variables that change in every shell and are not worth carrying over
+const IGNORED_VARIABLES: [&str; 7] = [
+    "_",
+    "SHLVL",
+    "PWD",
+    "OLDPWD",
+    "HAI_LAST_STATUS",
+    "HAI_LAST_COMMAND",
+    "HAI_STATE_FILE",
+];
+
+/// The working directory and exported variables of a shell at some point
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    pub cwd: PathBuf,
+    pub env: BTreeMap<String, String>,
+}
+
+/// A change a command made that the user's shell should repeat
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Cd(PathBuf),
+    Set(String, String),
+    Unset(String),
+}
+
+/// The state of this process, which is what the wrapped command's shell
+/// passes on to `hai __save-state`
+pub fn capture() -> Result<State> {
+    let cwd = std::env::current_dir().context("Failed to get the current directory")?;
+
+    // Variables that aren't valid UTF-8 can't be written back reliably
+    let env = std::env::vars_os()
+        .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+        .collect();
+
+    Ok(State { cwd, env })
+}
+
+/// Saves the state readable by the current user only, as the environment
+/// may hold secrets
+pub fn save(state: &State, path: &Path) -> Result<()> {
+    let json = serde_json::to_string(state).context("Failed to serialize shell state")?;
+    utils::write_private_file(path, &json)
+}
+
+pub fn load(path: &Path) -> Result<State> {
+    let json =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&json).context("Failed to parse shell state")
+}
+
+/// Wraps a command so that the shell's state is saved with `hai` before and
+/// after it runs, keeping the command's exit status. Returns `None` for
+/// shells whose syntax isn't supported.
+pub fn wrap(command: &str, shell: &str, hai: &Path, before: &Path, after: &Path) -> Option<String> {
+    let save = |path: &Path| {
+        format!(
+            "{} __save-state {}",
+            quote(&hai.to_string_lossy()),
+            quote(&path.to_string_lossy())
+        )
+    };
+
+    match shell {
+        "bash" | "zsh" | "sh" => Some(format!(
+            "{}\n{}\n__hai_status=$?\n{}\nexit $__hai_status\n",
+            save(before),
+            command,
+            save(after)
+        )),
+        "fish" => Some(format!(
+            "{}\n{}\nset __hai_status $status\n{}\nexit $__hai_status\n",
+            save(before),
+            command,
+            save(after)
+        )),
+        _ => None,
+    }
+}
+
+/// What changed between two states
+pub fn changes(before: &State, after: &State) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    if after.cwd != before.cwd {
+        changes.push(Change::Cd(after.cwd.clone()));
+    }
+
+    for (key, value) in &after.env {
+        if carried_over(key) && before.env.get(key) != Some(value) {
+            changes.push(Change::Set(key.clone(), value.clone()));
+        }
+    }
+    for key in before.env.keys() {
+        if carried_over(key) && !after.env.contains_key(key) {
+            changes.push(Change::Unset(key.clone()));
+        }
+    }
+
+    changes
+}
+
+/// Whether a variable can be set in the user's shell. Exported functions
+/// such as `BASH_FUNC_name%%` are left out.
+fn carried_over(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !IGNORED_VARIABLES.contains(&key)
+}
+
+/// Writes the changes as a script for the user's shell
+pub fn script(changes: &[Change], shell: &str) -> String {
+    let fish = shell == "fish";
+    let mut script = String::new();
+
+    for change in changes {
+        let line = match change {
+            Change::Cd(dir) => format!("cd {}", quote_for(&dir.to_string_lossy(), shell)),
+            Change::Set(key, value) if fish => {
+                format!("set -gx {} {}", key, quote_for(value, shell))
+            }
+            Change::Set(key, value) => format!("export {}={}", key, quote(value)),
+            Change::Unset(key) if fish => format!("set -e {}", key),
+            Change::Unset(key) => format!("unset {}", key),
+        };
+        script.push_str(&line);
+        script.push('\n');
+    }
+
+    script
+}
+
+/// Adds the changes to the file the shell hook sources before its next
+/// prompt. Only the current user can read it, as it may contain secrets.
+pub fn append_script(path: &Path, script: &str) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    file.write_all(script.as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Single-quotes a word for bash and zsh
+pub fn quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+/// Single-quotes a word for the given shell
+pub fn quote_for(word: &str, shell: &str) -> String {
+    if shell == "fish" {
+        format!("'{}'", word.replace('\\', r"\\").replace('\'', r"\'"))
+    } else {
+        quote(word)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use tempfile::tempdir;
+
+    fn state(cwd: &str, env: &[(&str, &str)]) -> State {
+        State {
+            cwd: PathBuf::from(cwd),
+            env: env
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_changes() {
+        let mut before = state(
+            "/home/me",
+            &[("PATH", "/bin"), ("SHLVL", "2"), ("OLD", "1")],
+        );
+        // Exported functions can't be set with export
+        before
+            .env
+            .insert("BASH_FUNC_f%%".to_string(), "() { :; }".to_string());
+        let after = state(
+            "/home/me/src",
+            &[("PATH", "/opt/bin:/bin"), ("SHLVL", "3"), ("NEW", "it's")],
+        );
+
+        let changes = changes(&before, &after);
+        assert_eq!(
+            changes,
+            vec![
+                Change::Cd(PathBuf::from("/home/me/src")),
+                Change::Set("NEW".to_string(), "it's".to_string()),
+                Change::Set("PATH".to_string(), "/opt/bin:/bin".to_string()),
+                Change::Unset("OLD".to_string()),
+            ]
+        );
+
+        assert_eq!(
+            script(&changes, "bash"),
+            "cd '/home/me/src'\nexport NEW='it'\\''s'\nexport PATH='/opt/bin:/bin'\nunset OLD\n"
+        );
+        assert_eq!(
+            script(&changes, "fish"),
+            "cd '/home/me/src'\nset -gx NEW 'it\\'s'\nset -gx PATH '/opt/bin:/bin'\nset -e OLD\n"
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let saved = state("/home/me", &[("TOKEN", "secret")]);
+        save(&saved, &path).unwrap();
+        assert_eq!(load(&path).unwrap().env, saved.env);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_wrap_keeps_exit_status() {
+        let dir = tempdir().unwrap();
+        let wrapped = wrap(
+            "exit_with() { return $1; }; exit_with 3",
+            "bash",
+            Path::new("true"),
+            &dir.path().join("before"),
+            &dir.path().join("after"),
+        )
+        .unwrap();
+
+        let status = Command::new("bash")
+            .arg("-c")
+            .arg(wrapped)
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(3));
+        assert!(wrap(
+            "dir",
+            "powershell",
+            Path::new("hai"),
+            dir.path(),
+            dir.path()
+        )
+        .is_none());
+    }
+}
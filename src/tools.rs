@@ -0,0 +1,208 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Programs worth telling the model about, because there is a common
+/// alternative it would otherwise pick, or because they decide how software
+/// gets installed
+#[rustfmt::skip]
+const TOOLS: [&str; 46] = [
+    // Search and text processing
+    "rg", "fd", "fdfind", "fzf", "jq", "yq", "gsed", "gawk", "gfind", "gxargs",
+    "bat", "batcat", "eza", "tree", "delta", "sd",
+    // Network
+    "curl", "wget", "rsync",
+    // Version control
+    "git", "gh",
+    // Containers
+    "docker", "podman", "kubectl", "helm",
+    // System package managers
+    "apt", "dnf", "yum", "pacman", "zypper", "apk", "brew", "port", "nix",
+    "snap", "flatpak", "winget", "choco", "scoop",
+    // Language package managers
+    "npm", "pnpm", "yarn", "pip3", "pipx", "uv", "cargo",
+];
+
+/// The tools found on the `PATH` the last time it was scanned
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Cache {
+    path: String,
+    tools: Vec<String>,
+}
+
+fn cache_path() -> Result<PathBuf> {
+    Ok(crate::utils::ensure_config_dir()?.join("tools.json"))
+}
+
+/// The installed tools, scanning the `PATH` only if it changed since the last
+/// scan
+pub fn installed() -> Vec<String> {
+    let path_var = env::var_os("PATH").unwrap_or_default();
+    match cache_path() {
+        Ok(cache_path) => installed_in(&cache_path, &path_var),
+        Err(_) => scan(&path_var),
+    }
+}
+
+/// The tools on `path_var`, from the cache at `cache_path` if it was made
+/// for the same `PATH`
+fn installed_in(cache_path: &Path, path_var: &OsStr) -> Vec<String> {
+    match load(cache_path) {
+        Some(cache) if OsStr::new(&cache.path) == path_var => cache.tools,
+        _ => {
+            let cache = Cache::scan(path_var);
+            // A cache that can't be written only means scanning again next time
+            let _ = save(cache_path, &cache);
+            cache.tools
+        }
+    }
+}
+
+/// Scans the `PATH` again, for `hai tools --refresh`
+pub fn refresh() -> Result<Vec<String>> {
+    refresh_in(&cache_path()?, &env::var_os("PATH").unwrap_or_default())
+}
+
+fn refresh_in(cache_path: &Path, path_var: &OsStr) -> Result<Vec<String>> {
+    let cache = Cache::scan(path_var);
+    save(cache_path, &cache)?;
+    Ok(cache.tools)
+}
+
+impl Cache {
+    fn scan(path_var: &OsStr) -> Cache {
+        Cache {
+            path: path_var.to_string_lossy().into_owned(),
+            tools: scan(path_var),
+        }
+    }
+}
+
+fn save(cache_path: &Path, cache: &Cache) -> Result<()> {
+    let json = serde_json::to_string_pretty(cache).context("Failed to serialize tool list")?;
+    fs::write(cache_path, json).with_context(|| format!("Failed to write {}", cache_path.display()))
+}
+
+fn load(cache_path: &Path) -> Option<Cache> {
+    let json = fs::read_to_string(cache_path).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+/// The tools in `TOOLS` that are in one of the directories of `path_var`
+fn scan(path_var: &OsStr) -> Vec<String> {
+    let dirs: Vec<PathBuf> = env::split_paths(path_var).collect();
+    TOOLS
+        .iter()
+        .filter(|tool| dirs.iter().any(|dir| is_executable(&dir.join(tool))))
+        .map(|tool| tool.to_string())
+        .collect()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    ["exe", "cmd", "bat", "ps1"]
+        .iter()
+        .any(|extension| path.with_extension(extension).is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn install(dir: &Path, name: &str) {
+        let path = dir.join(name);
+        fs::write(&path, "#!/bin/sh\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_scan() {
+        let bin = tempdir().unwrap();
+        let local = tempdir().unwrap();
+        install(bin.path(), "jq");
+        install(local.path(), "rg");
+        install(local.path(), "not-a-tool");
+        // Not executable
+        fs::write(local.path().join("fd"), "").unwrap();
+
+        let path_var = env::join_paths([bin.path(), local.path()]).unwrap();
+        assert_eq!(scan(&path_var), vec!["rg", "jq"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_cache() {
+        let dir = tempdir().unwrap();
+        let cache_path = dir.path().join("tools.json");
+        install(dir.path(), "docker");
+
+        let cache = Cache::scan(dir.path().as_os_str());
+        assert_eq!(cache.tools, vec!["docker"]);
+        save(&cache_path, &cache).unwrap();
+        assert_eq!(load(&cache_path), Some(cache));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_installed_in() {
+        let dir = tempdir().unwrap();
+        let cache_path = dir.path().join("tools.json");
+        let bin = tempdir().unwrap();
+        install(bin.path(), "git");
+
+        assert_eq!(
+            installed_in(&cache_path, bin.path().as_os_str()),
+            vec!["git"]
+        );
+        assert!(cache_path.is_file());
+
+        // The cache is used while the PATH stays the same
+        install(bin.path(), "gh");
+        assert_eq!(
+            installed_in(&cache_path, bin.path().as_os_str()),
+            vec!["git"]
+        );
+        let other = tempdir().unwrap();
+        let path_var = env::join_paths([bin.path(), other.path()]).unwrap();
+        assert_eq!(installed_in(&cache_path, &path_var), vec!["git", "gh"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_refresh_in() {
+        let dir = tempdir().unwrap();
+        let cache_path = dir.path().join("tools.json");
+        let bin = tempdir().unwrap();
+        install(bin.path(), "git");
+        assert_eq!(
+            installed_in(&cache_path, bin.path().as_os_str()),
+            vec!["git"]
+        );
+
+        // A refresh scans even though the PATH is the same
+        install(bin.path(), "gh");
+        assert_eq!(
+            refresh_in(&cache_path, bin.path().as_os_str()).unwrap(),
+            vec!["git", "gh"]
+        );
+        assert_eq!(
+            installed_in(&cache_path, bin.path().as_os_str()),
+            vec!["git", "gh"]
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use crate::config::Config;
+
+/// Shells that commands can be translated to and from
+pub const SHELLS: [&str; 5] = ["bash", "zsh", "fish", "pwsh", "powershell"];
+
+/// Room for a translated one-liner; scripts get more, see `max_tokens`
+const MIN_MAX_TOKENS: usize = 1024;
+
+/// Providers refuse requests for longer answers than a model can give, and
+/// current models can all give this much
+const MAX_MAX_TOKENS: usize = 8192;
+
+/// System prompt for `hai translate`, completed with the two shells
+const TRANSLATE_SYSTEM_PROMPT: &str = "
+You are Hai, a helpful AI that translates shell commands and scripts between shells.
+Translate the user's input from {from} to {to} so that it behaves the same way.
+Respond with ONLY the translated code, no explanations or markdown formatting.
+Keep comments, translating them only where they describe shell syntax.
+If something has no equivalent in {to}, write the closest alternative and add a comment explaining the difference.
+";
+
+/// The system prompt for translating from one shell to another. The
+/// environment describes the target shell.
+pub fn system_prompt(config: &Config, from: &str, to: &str) -> String {
+    let base_prompt = TRANSLATE_SYSTEM_PROMPT
+        .replace("{from}", shell_name(from))
+        .replace("{to}", shell_name(to));
+    config.with_shell(to).with_environment(&base_prompt)
+}
+
+/// A translation is about as long as its input. A token is three or four
+/// characters of code, so two characters per token leaves room for a
+/// translation that comes out longer.
+pub fn max_tokens(input: &str) -> usize {
+    (input.chars().count() / 2).clamp(MIN_MAX_TOKENS, MAX_MAX_TOKENS)
+}
+
+fn shell_name(shell: &str) -> &str {
+    match shell {
+        "bash" => "Bash",
+        "zsh" => "Zsh",
+        "fish" => "Fish",
+        "pwsh" => "PowerShell Core",
+        "powershell" => "Windows PowerShell",
+        _ => shell,
+    }
+}
+
+/// Guesses a script's shell from its `#!` line
+pub fn detect_shell(script: &str) -> Option<&'static str> {
+    let shebang = script.lines().next()?.strip_prefix("#!")?;
+    let mut words = shebang.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-'))?;
+    }
+
+    match program {
+        "bash" | "sh" => Some("bash"),
+        "zsh" => Some("zsh"),
+        "fish" => Some("fish"),
+        "pwsh" => Some("pwsh"),
+        _ => None,
+    }
+}
+
+/// Removes the markdown code fence models sometimes wrap code in anyway
+pub fn strip_code_fence(response: &str) -> &str {
+    let trimmed = response.trim();
+    let Some(rest) = trimmed.strip_prefix("```") else {
+        return trimmed;
+    };
+
+    // Drop the language after the opening fence
+    let body = rest.split_once('\n').map_or("", |(_, body)| body);
+    body.trim_end()
+        .strip_suffix("```")
+        .unwrap_or(body)
+        .trim_matches('\n')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cli, Commands};
+    use clap::Parser;
+
+    #[test]
+    fn test_system_prompt() {
+        let prompt = system_prompt(&Config::default().without_tool_detection(), "bash", "fish");
+        assert!(prompt.contains("from Bash to Fish"));
+        assert!(prompt.contains("Shell: Fish shell (fish)"));
+    }
+
+    #[test]
+    fn test_max_tokens() {
+        assert_eq!(max_tokens("ls -la"), MIN_MAX_TOKENS);
+        assert_eq!(max_tokens(&"echo hi\n".repeat(1000)), 4000);
+        assert_eq!(max_tokens(&"echo hi\n".repeat(100_000)), MAX_MAX_TOKENS);
+    }
+
+    #[test]
+    fn test_detect_shell() {
+        assert_eq!(detect_shell("#!/bin/bash\necho hi\n"), Some("bash"));
+        assert_eq!(detect_shell("#!/usr/bin/env -S zsh -f\n"), Some("zsh"));
+        assert_eq!(detect_shell("#!/usr/bin/env fish\n"), Some("fish"));
+        assert_eq!(detect_shell("#!/usr/bin/python3\n"), None);
+        assert_eq!(detect_shell("echo no shebang\n"), None);
+    }
+
+    #[test]
+    fn test_strip_code_fence() {
+        assert_eq!(strip_code_fence("ls -la\n"), "ls -la");
+        assert_eq!(
+            strip_code_fence("```fish\nfor f in *\n    echo $f\nend\n```"),
+            "for f in *\n    echo $f\nend"
+        );
+    }
+
+    #[test]
+    fn test_cli_translate() {
+        let cli = Cli::try_parse_from(["hai", "translate", "--to", "fish", "export A=1"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Translate { ref to, from: None, file: None, command: Some(_) })
+                if to == "fish"
+        ));
+        assert!(Cli::try_parse_from(["hai", "translate", "--to", "csh", "ls"]).is_err());
+    }
+}
@@ -0,0 +1,317 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::HaiError;
+use crate::preview::{Action, Target};
+use crate::utils::{self, copy_recursive, disk_usage, remove_recursive};
+
+/// Snapshots larger than this are skipped rather than filling up the disk
+const MAX_SNAPSHOT_BYTES: u64 = 100 * 1024 * 1024;
+
+/// Files saved to the trash before a command removed or overwrote them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub id: String,
+    pub files: Vec<SavedFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedFile {
+    /// Absolute path the file is restored to
+    pub original: PathBuf,
+    /// Name of the copy inside the snapshot directory
+    pub stored: String,
+    /// Where the command moved the file, which is removed when it is
+    /// restored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moved_to: Option<PathBuf>,
+}
+
+/// The snapshot as it was taken, kept next to the copies. The history only
+/// has the paths with secrets redacted, which can't be restored to.
+const MANIFEST: &str = "snapshot.json";
+
+/// Returns the trash directory inside the config directory
+fn trash_dir() -> Result<PathBuf> {
+    let dir = utils::ensure_config_dir()?.join("trash");
+    fs::create_dir_all(&dir).context("Failed to create trash directory")?;
+    Ok(dir)
+}
+
+/// Copies the files a command is about to delete, move or change into the
+/// trash. Returns `None` if the command does not touch any existing file.
+pub fn snapshot(targets: &[Target], cwd: &Path) -> Result<Option<Snapshot>> {
+    snapshot_in(&trash_dir()?, targets, cwd)
+}
+
+/// Puts every file in a snapshot back where it was
+pub fn restore(snapshot: &Snapshot) -> Result<()> {
+    restore_from(&trash_dir()?, snapshot)
+}
+
+/// The snapshot with the paths as they were taken, rather than as redacted
+/// in the history
+pub fn unredacted(snapshot: &Snapshot) -> Snapshot {
+    match trash_dir() {
+        Ok(dir) => load_manifest(&dir, snapshot),
+        Err(_) => snapshot.clone(),
+    }
+}
+
+fn load_manifest(trash_dir: &Path, snapshot: &Snapshot) -> Snapshot {
+    fs::read_to_string(trash_dir.join(&snapshot.id).join(MANIFEST))
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_else(|| snapshot.clone())
+}
+
+/// Deletes snapshots that are no longer referenced, e.g. because their history
+/// entry was trimmed, purged or already restored
+pub fn prune<'a>(keep: impl IntoIterator<Item = &'a Snapshot>) -> Result<()> {
+    let dir = utils::ensure_config_dir()?.join("trash");
+    if !dir.exists() {
+        return Ok(());
+    }
+    prune_in(&dir, keep)
+}
+
+fn snapshot_in(trash_dir: &Path, targets: &[Target], cwd: &Path) -> Result<Option<Snapshot>> {
+    let mut seen = HashSet::new();
+    let originals: Vec<(PathBuf, Option<PathBuf>)> = targets
+        .iter()
+        .filter(|t| t.action != Action::Create)
+        .map(|t| {
+            (
+                cwd.join(&t.path),
+                t.destination.as_ref().map(|d| cwd.join(d)),
+            )
+        })
+        .filter(|(path, _)| path.symlink_metadata().is_ok())
+        .filter(|(path, _)| seen.insert(path.clone()))
+        .collect();
+
+    if originals.is_empty() {
+        return Ok(None);
+    }
+
+    let size: u64 = originals.iter().map(|(path, _)| disk_usage(path)).sum();
+    if size > MAX_SNAPSHOT_BYTES {
+        return Err(HaiError::other(format!(
+            "The files are too large to save to the trash ({} MB, the limit is {} MB)",
+            size / 1024 / 1024,
+            MAX_SNAPSHOT_BYTES / 1024 / 1024
+        )));
+    }
+
+    let timestamp = chrono::Utc::now().format("%Y%m%dT%H%M%S%.6f").to_string();
+    let mut id = timestamp.clone();
+    for n in 1.. {
+        if !trash_dir.join(&id).exists() {
+            break;
+        }
+        id = format!("{}-{}", timestamp, n);
+    }
+    let snapshot_dir = trash_dir.join(&id);
+    fs::create_dir_all(&snapshot_dir).context("Failed to create snapshot directory")?;
+
+    let mut files = Vec::new();
+    for (i, (original, moved_to)) in originals.into_iter().enumerate() {
+        // Numbered rather than named after the file, so that the history
+        // doesn't repeat a name that holds a secret
+        let stored = i.to_string();
+
+        copy_recursive(&original, &snapshot_dir.join(&stored))
+            .with_context(|| format!("Failed to save {} to the trash", original.display()))?;
+
+        files.push(SavedFile {
+            original,
+            stored,
+            moved_to,
+        });
+    }
+
+    let snapshot = Snapshot { id, files };
+    let manifest = serde_json::to_string(&snapshot).context("Failed to serialize snapshot")?;
+    utils::write_private_file(&snapshot_dir.join(MANIFEST), &manifest)?;
+    Ok(Some(snapshot))
+}
+
+fn restore_from(trash_dir: &Path, snapshot: &Snapshot) -> Result<()> {
+    let snapshot_dir = trash_dir.join(&snapshot.id);
+    if !snapshot_dir.is_dir() {
+        return Err(HaiError::io(format!(
+            "Snapshot {} is missing from the trash",
+            snapshot.id
+        )));
+    }
+
+    let snapshot = load_manifest(trash_dir, snapshot);
+
+    // Moved copies go first, as a file may have been moved over another one
+    // that is restored below
+    for moved_to in snapshot.files.iter().filter_map(|f| f.moved_to.as_ref()) {
+        if moved_to.symlink_metadata().is_ok() {
+            remove_recursive(moved_to)
+                .with_context(|| format!("Failed to remove {}", moved_to.display()))?;
+        }
+    }
+
+    for file in &snapshot.files {
+        if file.original.symlink_metadata().is_ok() {
+            remove_recursive(&file.original)
+                .with_context(|| format!("Failed to replace {}", file.original.display()))?;
+        }
+        if let Some(parent) = file.original.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        copy_recursive(&snapshot_dir.join(&file.stored), &file.original)
+            .with_context(|| format!("Failed to restore {}", file.original.display()))?;
+    }
+
+    Ok(())
+}
+
+fn prune_in<'a>(trash_dir: &Path, keep: impl IntoIterator<Item = &'a Snapshot>) -> Result<()> {
+    let keep: HashSet<&str> = keep.into_iter().map(|s| s.id.as_str()).collect();
+
+    for entry in fs::read_dir(trash_dir).context("Failed to read trash directory")? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if !keep.contains(name.to_string_lossy().as_ref()) {
+            remove_recursive(&entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn target(action: Action, path: &str) -> Target {
+        Target {
+            action,
+            path: PathBuf::from(path),
+            destination: None,
+        }
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let trash = tempdir().unwrap();
+        let work = tempdir().unwrap();
+        fs::write(work.path().join("notes.txt"), "original").unwrap();
+        fs::create_dir_all(work.path().join("build/out")).unwrap();
+        fs::write(work.path().join("build/out/app"), "binary").unwrap();
+
+        let targets = vec![
+            target(Action::Overwrite, "notes.txt"),
+            target(Action::Delete, "build"),
+            target(Action::Create, "new.txt"),
+            target(Action::Delete, "missing.txt"),
+        ];
+        let snapshot = snapshot_in(trash.path(), &targets, work.path())
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.files.len(), 2);
+        assert_eq!(snapshot.files[0].original, work.path().join("notes.txt"));
+
+        // Simulate the command running
+        fs::write(work.path().join("notes.txt"), "clobbered").unwrap();
+        fs::remove_dir_all(work.path().join("build")).unwrap();
+
+        restore_from(trash.path(), &snapshot).unwrap();
+        assert_eq!(
+            fs::read_to_string(work.path().join("notes.txt")).unwrap(),
+            "original"
+        );
+        assert_eq!(
+            fs::read_to_string(work.path().join("build/out/app")).unwrap(),
+            "binary"
+        );
+    }
+
+    #[test]
+    fn test_restore_move() {
+        let trash = tempdir().unwrap();
+        let work = tempdir().unwrap();
+        fs::write(work.path().join("a.txt"), "a").unwrap();
+        fs::write(work.path().join("b.txt"), "b").unwrap();
+
+        // mv a.txt b.txt, which also overwrites b.txt
+        let targets = vec![
+            Target {
+                action: Action::Move,
+                path: PathBuf::from("a.txt"),
+                destination: Some(PathBuf::from("b.txt")),
+            },
+            target(Action::Overwrite, "b.txt"),
+        ];
+        let snapshot = snapshot_in(trash.path(), &targets, work.path())
+            .unwrap()
+            .unwrap();
+        fs::rename(work.path().join("a.txt"), work.path().join("b.txt")).unwrap();
+
+        // The history only keeps redacted paths
+        let mut redacted = snapshot.clone();
+        for file in &mut redacted.files {
+            file.original = PathBuf::from("[REDACTED]");
+        }
+        restore_from(trash.path(), &redacted).unwrap();
+        assert_eq!(fs::read_to_string(work.path().join("a.txt")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(work.path().join("b.txt")).unwrap(), "b");
+
+        // A move to a new name leaves no copy behind
+        let targets = vec![Target {
+            action: Action::Move,
+            path: PathBuf::from("a.txt"),
+            destination: Some(PathBuf::from("c.txt")),
+        }];
+        let snapshot = snapshot_in(trash.path(), &targets, work.path())
+            .unwrap()
+            .unwrap();
+        fs::rename(work.path().join("a.txt"), work.path().join("c.txt")).unwrap();
+        restore_from(trash.path(), &snapshot).unwrap();
+        assert!(work.path().join("a.txt").exists());
+        assert!(!work.path().join("c.txt").exists());
+    }
+
+    #[test]
+    fn test_nothing_to_snapshot() {
+        let trash = tempdir().unwrap();
+        let work = tempdir().unwrap();
+
+        let targets = vec![target(Action::Create, "out.txt")];
+        assert!(snapshot_in(trash.path(), &targets, work.path())
+            .unwrap()
+            .is_none());
+        assert_eq!(fs::read_dir(trash.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_prune() {
+        let trash = tempdir().unwrap();
+        let work = tempdir().unwrap();
+        fs::write(work.path().join("a.txt"), "a").unwrap();
+
+        let targets = vec![target(Action::Delete, "a.txt")];
+        let first = snapshot_in(trash.path(), &targets, work.path())
+            .unwrap()
+            .unwrap();
+        let second = snapshot_in(trash.path(), &targets, work.path())
+            .unwrap()
+            .unwrap();
+
+        prune_in(trash.path(), [&second]).unwrap();
+        assert!(!trash.path().join(&first.id).exists());
+        assert!(trash.path().join(&second.id).exists());
+
+        assert!(restore_from(trash.path(), &first).is_err());
+    }
+}
@@ -2,7 +2,10 @@ use anyhow::{Context, Result};
 use dialoguer::{theme::ColorfulTheme, Input, Select};
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::secrets;
 
 /// Ensures that the config directory exists
 pub fn ensure_config_dir() -> Result<PathBuf> {
@@ -17,15 +20,110 @@ pub fn ensure_config_dir() -> Result<PathBuf> {
     Ok(config_dir)
 }
 
+/// Restricts a file so that only the current user can read or write it
+#[cfg(unix)]
+pub fn restrict_permissions(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = fs::metadata(path)
+        .with_context(|| format!("Failed to read permissions of {}", path.display()))?;
+
+    if metadata.permissions().mode() & 0o777 != 0o600 {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("Failed to set permissions on {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Restricts a file so that only the current user can read or write it
+#[cfg(not(unix))]
+pub fn restrict_permissions(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Writes a file that should only be readable by the current user, such as
+/// the config file (which holds API keys) or the history file. The contents
+/// go to a new private file next to it, which then replaces it, so they are
+/// never readable by others even if the old file was.
+pub fn write_private_file(path: &Path, contents: &str) -> Result<()> {
+    // Replace the file a symlink points to rather than the symlink
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    // Temporary files are created readable by the current user only
+    let mut file = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("Failed to create a file in {}", dir.display()))?;
+    file.write_all(contents.as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    file.persist(&path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+/// Copies a file, symlink or directory tree
+pub fn copy_recursive(from: &Path, to: &Path) -> std::io::Result<()> {
+    let metadata = from.symlink_metadata()?;
+
+    if metadata.file_type().is_symlink() {
+        #[cfg(unix)]
+        return std::os::unix::fs::symlink(fs::read_link(from)?, to);
+        #[cfg(not(unix))]
+        return fs::copy(from, to).map(|_| ());
+    }
+
+    if metadata.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        fs::set_permissions(to, metadata.permissions())
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+/// Removes a file, symlink or directory tree
+pub fn remove_recursive(path: &Path) -> std::io::Result<()> {
+    if path.symlink_metadata()?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Returns the total size in bytes of a file or directory tree
+pub fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = path.symlink_metadata() else {
+        return 0;
+    };
+
+    if metadata.is_dir() {
+        fs::read_dir(path)
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| disk_usage(&e.path()))
+                    .sum()
+            })
+            .unwrap_or(0)
+    } else {
+        metadata.len()
+    }
+}
+
 /// Returns the base configuration template
 fn get_base_config() -> String {
-    format!(
-        r#"# Global settings
+    r#"# Global settings
 history-size = 50
 
 # Model configurations
 "#
-    )
+    .to_string()
 }
 
 /// Creates a default config file if it doesn't exist
@@ -50,7 +148,7 @@ auth-token = ""  # Add your Anthropic API key here
 "#,
         );
 
-        std::fs::write(config_path, config)?;
+        write_private_file(&config_path, &config)?;
     }
 
     Ok(())
@@ -96,12 +194,7 @@ pub fn guide_initial_setup() -> Result<()> {
                 .allow_empty(true)
                 .interact_text()?;
 
-            if api_key.is_empty() {
-                config.push_str("auth-token = \"\"\n");
-                println!("You'll need to edit the config file later to add your API key.");
-            } else {
-                config.push_str(&format!("auth-token = \"{}\"\n", api_key));
-            }
+            config.push_str(&auth_token_setting("openai", &api_key));
         }
         1 => {
             // Anthropic
@@ -116,12 +209,7 @@ pub fn guide_initial_setup() -> Result<()> {
                 .allow_empty(true)
                 .interact_text()?;
 
-            if api_key.is_empty() {
-                config.push_str("auth-token = \"\"\n");
-                println!("You'll need to edit the config file later to add your API key.");
-            } else {
-                config.push_str(&format!("auth-token = \"{}\"\n", api_key));
-            }
+            config.push_str(&auth_token_setting("anthropic", &api_key));
         }
         _ => {
             // Skip
@@ -135,10 +223,94 @@ pub fn guide_initial_setup() -> Result<()> {
         }
     }
 
-    fs::write(&config_path, config).context("Failed to write config file")?;
+    write_private_file(&config_path, &config).context("Failed to write config file")?;
 
     println!("\nConfiguration file created at: {}", config_path.display());
     println!("You can edit this file anytime to change your settings.");
 
     Ok(())
 }
+
+/// The config lines for an API key entered during setup. The key goes to the
+/// system keyring, and is never written to the config file: without a
+/// keyring, the user is told how to provide it instead.
+fn auth_token_setting(provider: &str, api_key: &str) -> String {
+    if api_key.is_empty() {
+        println!("You'll need to edit the config file later to add your API key.");
+        return "auth-token = \"\"\n".to_string();
+    }
+
+    let stored = if secrets::KEYRING_SUPPORTED {
+        secrets::store_in_keyring(provider, api_key)
+    } else {
+        Err(anyhow::anyhow!(
+            "there is no keyring support on this system"
+        ))
+    };
+    match stored {
+        Ok(()) => {
+            println!("Your API key is stored in the system keyring.");
+            "auth-token-keyring = true\n".to_string()
+        }
+        Err(e) => {
+            let var = format!("HAI_{}_TOKEN", provider.to_uppercase());
+            let api_key_var = format!("{}_API_KEY", provider.to_uppercase());
+            println!(
+                "Couldn't store your API key in the system keyring ({:#}), and it isn't saved in the config file.",
+                e
+            );
+            println!(
+                "Set {} in your environment, or set auth-token-env or auth-token-command in the config file.",
+                var
+            );
+            format!(
+                "auth-token = \"\"\n\
+                 # Instead of auth-token, read the key from a variable or a command:\n\
+                 # auth-token-env = \"{}\"\n\
+                 # auth-token-command = \"pass show {}\"\n",
+                api_key_var, provider
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_private_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("secret.txt");
+
+        write_private_file(&path, "first").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // Existing files with loose permissions are tightened on rewrite
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_private_file(&path, "second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_private_file_through_symlink() {
+        let temp_dir = tempdir().unwrap();
+        let target = temp_dir.path().join("config.toml");
+        let link = temp_dir.path().join("link.toml");
+        fs::write(&target, "old").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        write_private_file(&link, "new").unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+    }
+}
@@ -30,4 +30,19 @@ mod tests {
         assert!(stdout.contains("hai"));
         assert!(stdout.contains("0.2.0"));
     }
+
+    #[test]
+    fn test_completions_subcommand() {
+        let output = Command::new("cargo")
+            .args(["run", "--", "completions", "bash"])
+            .output()
+            .expect("Failed to execute command");
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        assert!(output.status.success());
+        assert!(stdout.contains("complete -F _hai"));
+        assert!(stdout.contains("--no-execute"));
+        assert!(stdout.contains("translate"));
+    }
 }